@@ -0,0 +1,399 @@
+use serde::Serialize;
+
+// Euler-Mascheroni constant, the mean of the standard Gumbel distribution
+const EULER_GAMMA: f64 = 0.577_215_664_901_532_9;
+
+/// Natural logarithm of the gamma function for positive `x`, by the
+/// Lanczos approximation (g = 7, 9 terms)
+pub fn ln_gamma(x: f64) -> f64 {
+    const COEFFICIENTS: [f64; 9] = [
+        0.999_999_999_999_809_9,
+        676.520_368_121_885_1,
+        -1_259.139_216_722_402_8,
+        771.323_428_777_653_1,
+        -176.615_029_162_140_6,
+        12.507_343_278_686_905,
+        -0.138_571_095_265_720_12,
+        9.984_369_578_019_572e-6,
+        1.505_632_735_149_311_6e-7,
+    ];
+
+    if x < 0.5 {
+        // reflection formula
+        let pi = std::f64::consts::PI;
+        return (pi / (pi * x).sin()).ln() - ln_gamma(1.0 - x);
+    }
+
+    let x = x - 1.0;
+    let t = x + 7.5;
+    let sum = COEFFICIENTS[1..]
+        .iter()
+        .enumerate()
+        .fold(COEFFICIENTS[0], |s, (i, c)| s + c / (x + i as f64 + 1.0));
+
+    0.5 * (2.0 * std::f64::consts::PI).ln() + (x + 0.5) * t.ln() - t + sum.ln()
+}
+
+/// The regularized upper incomplete gamma function `Q(a, x)`, by its
+/// series for `x < a + 1` and its continued fraction otherwise
+pub fn gamma_q(a: f64, x: f64) -> f64 {
+    const EPSILON: f64 = 1e-15;
+    const MAX_ITERATIONS: usize = 1000;
+
+    if x <= 0.0 {
+        return 1.0;
+    }
+    let prefactor = (a * x.ln() - x - ln_gamma(a)).exp();
+
+    if x < a + 1.0 {
+        // P(a, x) as a series
+        let mut term = 1.0 / a;
+        let mut sum = term;
+        for n in 1..MAX_ITERATIONS {
+            term *= x / (a + n as f64);
+            sum += term;
+            if term.abs() < sum.abs() * EPSILON {
+                break;
+            }
+        }
+        1.0 - sum * prefactor
+    } else {
+        // modified Lentz's method
+        let tiny = 1e-300;
+        let mut b = x + 1.0 - a;
+        let mut c = 1.0 / tiny;
+        let mut d = 1.0 / b;
+        let mut h = d;
+        for i in 1..MAX_ITERATIONS {
+            let an = -(i as f64) * (i as f64 - a);
+            b += 2.0;
+            d = an * d + b;
+            if d.abs() < tiny {
+                d = tiny;
+            }
+            c = b + an / c;
+            if c.abs() < tiny {
+                c = tiny;
+            }
+            d = 1.0 / d;
+            let delta = d * c;
+            h *= delta;
+            if (delta - 1.0).abs() < EPSILON {
+                break;
+            }
+        }
+        prefactor * h
+    }
+}
+
+/// The probability that a chi-square variable with `df` degrees of
+/// freedom is greater than `x`
+pub fn chi_square_survival(x: f64, df: f64) -> f64 {
+    gamma_q(df / 2.0, x / 2.0)
+}
+
+/// The regularized incomplete beta function `I_x(a, b)`, by its continued
+/// fraction
+pub fn beta_i(a: f64, b: f64, x: f64) -> f64 {
+    if x <= 0.0 {
+        return 0.0;
+    }
+    if x >= 1.0 {
+        return 1.0;
+    }
+
+    let prefactor = (ln_gamma(a + b) - ln_gamma(a) - ln_gamma(b)
+        + a * x.ln()
+        + b * (1.0 - x).ln())
+    .exp();
+
+    // the continued fraction converges quickly below this point, and the
+    // symmetry I_x(a, b) = 1 - I_{1-x}(b, a) covers the rest
+    if x < (a + 1.0) / (a + b + 2.0) {
+        prefactor * beta_fraction(a, b, x) / a
+    } else {
+        1.0 - prefactor * beta_fraction(b, a, 1.0 - x) / b
+    }
+}
+
+// the continued fraction of the incomplete beta function, by the modified
+// Lentz's method
+fn beta_fraction(a: f64, b: f64, x: f64) -> f64 {
+    const EPSILON: f64 = 1e-15;
+    const MAX_ITERATIONS: usize = 1000;
+    let tiny = 1e-300;
+
+    let clamp = |v: f64| if v.abs() < tiny { tiny } else { v };
+
+    let mut c = 1.0;
+    let mut d = 1.0 / clamp(1.0 - (a + b) * x / (a + 1.0));
+    let mut h = d;
+    for m in 1..MAX_ITERATIONS {
+        let m = m as f64;
+        let m2 = 2.0 * m;
+
+        let an = m * (b - m) * x / ((a + m2 - 1.0) * (a + m2));
+        d = 1.0 / clamp(1.0 + an * d);
+        c = clamp(1.0 + an / c);
+        h *= d * c;
+
+        let an = -(a + m) * (a + b + m) * x / ((a + m2) * (a + m2 + 1.0));
+        d = 1.0 / clamp(1.0 + an * d);
+        c = clamp(1.0 + an / c);
+        let delta = d * c;
+        h *= delta;
+
+        if (delta - 1.0).abs() < EPSILON {
+            break;
+        }
+    }
+    h
+}
+
+/// The probability that a Student's t variable with `df` degrees of
+/// freedom is greater than `t`
+pub fn student_t_survival(t: f64, df: f64) -> f64 {
+    let tail = 0.5 * beta_i(df / 2.0, 0.5, df / (df + t * t));
+    if t > 0.0 {
+        tail
+    } else {
+        1.0 - tail
+    }
+}
+
+// the value that a standard normal variable exceeds with probability
+// `p` (at most 0.5), to within 4.5e-4 (Abramowitz & Stegun 26.2.23)
+fn normal_quantile_approx(p: f64) -> f64 {
+    let t = (-2.0 * p.ln()).sqrt();
+    t - (2.515_517 + 0.802_853 * t + 0.010_328 * t * t)
+        / (1.0 + 1.432_788 * t + 0.189_269 * t * t + 0.001_308 * t * t * t)
+}
+
+/// The value that a Student's t variable with `df` degrees of freedom
+/// exceeds with probability `p`, by Newton's method from the
+/// Cornish-Fisher expansion of the normal quantile
+pub fn student_t_quantile(p: f64, df: f64) -> f64 {
+    if p > 0.5 {
+        return -student_t_quantile(1.0 - p, df);
+    }
+    if p == 0.5 {
+        return 0.0;
+    }
+    // the Cauchy and df = 2 distributions have closed forms, and their
+    // tails are too heavy for the expansion
+    if df == 1.0 {
+        return (std::f64::consts::PI * (0.5 - p)).tan();
+    }
+    if df == 2.0 {
+        return (1.0 - 2.0 * p) / (2.0 * p * (1.0 - p)).sqrt();
+    }
+
+    let z = normal_quantile_approx(p);
+    let (z2, v) = (z * z, df);
+    let mut t = z
+        + z * (z2 + 1.0) / (4.0 * v)
+        + z * ((5.0 * z2 + 16.0) * z2 + 3.0) / (96.0 * v * v)
+        + z * (((3.0 * z2 + 19.0) * z2 + 17.0) * z2 - 15.0)
+            / (384.0 * v * v * v);
+
+    // the density is `norm (1 + t^2 / df)^(-(df + 1) / 2)`; the
+    // survival function is convex above zero, so the steps converge
+    // monotonically after the first
+    let ln_norm = ln_gamma((v + 1.0) / 2.0)
+        - ln_gamma(v / 2.0)
+        - 0.5 * (v * std::f64::consts::PI).ln();
+    for _ in 0..50 {
+        let density = (ln_norm - 0.5 * (v + 1.0) * (t * t / v).ln_1p()).exp();
+        let step = (student_t_survival(t, v) - p) / density;
+        // a step past zero leaves the convex part of the survival
+        t = if t + step > 0.0 { t + step } else { 0.5 * t };
+        if step.abs() <= 1e-12 * t {
+            break;
+        }
+    }
+    t
+}
+
+/// A generalized extreme value distribution, with distribution function
+/// `exp(-(1 + shape (x - location) / scale)^(-1 / shape))`. A shape of
+/// zero is the Gumbel distribution; a negative shape bounds the
+/// distribution above.
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct Gev {
+    pub location: f64,
+    pub scale: f64,
+    pub shape: f64,
+}
+
+impl Gev {
+    /// Fits a GEV distribution to sorted `maxima` by probability-weighted
+    /// moments (Hosking, Wallis & Wood 1985). Returns `None` if there are
+    /// fewer than three maxima, they are all equal, or the fitted tail is
+    /// too heavy for the moments to exist.
+    pub fn fit(maxima: &[f64]) -> Option<Gev> {
+        let n = maxima.len();
+        if n < 3 {
+            return None;
+        }
+        let nf = n as f64;
+
+        let mut b0 = 0.0;
+        let mut b1 = 0.0;
+        let mut b2 = 0.0;
+        for (i, x) in maxima.iter().enumerate() {
+            let i = i as f64;
+            b0 += x;
+            b1 += x * i / (nf - 1.0);
+            b2 += x * i * (i - 1.0) / ((nf - 1.0) * (nf - 2.0));
+        }
+        b0 /= nf;
+        b1 /= nf;
+        b2 /= nf;
+
+        let l2 = 2.0 * b1 - b0;
+        if l2 <= 0.0 {
+            return None;
+        }
+
+        // Hosking's k, which is the negative of the shape
+        let c = l2 / (3.0 * b2 - b0) - 2f64.ln() / 3f64.ln();
+        let k = 7.8590 * c + 2.9554 * c * c;
+
+        let (scale, location) = if k.abs() < 1e-6 {
+            let scale = l2 / 2f64.ln();
+            (scale, b0 - EULER_GAMMA * scale)
+        } else if k > -1.0 {
+            let gamma = ln_gamma(1.0 + k).exp();
+            let scale = l2 * k / (gamma * (1.0 - 2f64.powf(-k)));
+            (scale, b0 + scale * (gamma - 1.0) / k)
+        } else {
+            return None;
+        };
+
+        if !scale.is_finite() || scale <= 0.0 || !location.is_finite() {
+            return None;
+        }
+
+        Some(Gev {
+            location,
+            scale,
+            shape: -k,
+        })
+    }
+
+    /// The probability of a value greater than `x`
+    pub fn survival(&self, x: f64) -> f64 {
+        let z = (x - self.location) / self.scale;
+        let t = if self.shape.abs() < 1e-6 {
+            (-z).exp()
+        } else {
+            let base = 1.0 + self.shape * z;
+            if base <= 0.0 {
+                // beyond the upper bound, or below the lower one
+                return if self.shape < 0.0 { 0.0 } else { 1.0 };
+            }
+            base.powf(-1.0 / self.shape)
+        };
+        // 1 - exp(-t), without cancellation for small t
+        -(-t).exp_m1()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_can_compute_ln_gamma() {
+        assert!(ln_gamma(1.0).abs() < 1e-12);
+        assert!(ln_gamma(2.0).abs() < 1e-12);
+        assert!((ln_gamma(5.0) - 24f64.ln()).abs() < 1e-12);
+        assert!(
+            (ln_gamma(0.5) - std::f64::consts::PI.sqrt().ln()).abs() < 1e-12
+        );
+    }
+
+    #[test]
+    fn it_can_compute_chi_square_tails() {
+        // two degrees of freedom give an exponential tail
+        for &x in [0.5, 3.0, 40.0].iter() {
+            let p = chi_square_survival(x, 2.0);
+            assert!((p - (-x / 2.0).exp()).abs() < 1e-12 * p.max(1e-300));
+        }
+        assert!(
+            (chi_square_survival(3.841_458_820_694_124, 1.0) - 0.05).abs()
+                < 1e-9
+        );
+        assert!(
+            (chi_square_survival(6.634_896_601_021_214, 1.0) - 0.01).abs()
+                < 1e-9
+        );
+        assert_eq!(1.0, chi_square_survival(0.0, 1.0));
+    }
+
+    #[test]
+    fn it_can_compute_student_t_quantiles() {
+        assert!(
+            (student_t_quantile(0.025, 10.0) - 2.228_138_851_986_274).abs()
+                < 1e-9
+        );
+        assert!(
+            (student_t_quantile(0.025, 1.0) - 12.706_204_736_174_7).abs()
+                < 1e-7
+        );
+        assert!(student_t_quantile(0.5, 5.0).abs() < 1e-6);
+        assert!(
+            (student_t_quantile(0.025, 2.0) - 4.302_652_729_749_464).abs()
+                < 1e-9
+        );
+        assert!(
+            (student_t_quantile(0.025, 3.0) - 3.182_446_305_284_263).abs()
+                < 1e-9
+        );
+        assert!(
+            (student_t_quantile(0.975, 30.0) + 2.042_272_456_301_238).abs()
+                < 1e-9
+        );
+        for &df in [3.0, 4.5, 8.0, 25.0, 200.0].iter() {
+            for &p in [1e-6, 0.001, 0.025, 0.2].iter() {
+                let t = student_t_quantile(p, df);
+                assert!((student_t_survival(t, df) / p - 1.0).abs() < 1e-9);
+            }
+        }
+        assert!(
+            (student_t_survival(-2.0, 3.0) + student_t_survival(2.0, 3.0)
+                - 1.0)
+                .abs()
+                < 1e-12
+        );
+    }
+
+    #[test]
+    fn it_can_fit_gumbel_maxima() {
+        // the quantiles of a Gumbel distribution with location 10 and
+        // scale 2
+        let n = 1000;
+        let maxima: Vec<_> = (0..n)
+            .map(|i| {
+                let u = (i as f64 + 0.5) / n as f64;
+                10.0 - 2.0 * (-u.ln()).ln()
+            })
+            .collect();
+
+        let gev = Gev::fit(&maxima).unwrap();
+        assert!((gev.location - 10.0).abs() < 0.05);
+        assert!((gev.scale - 2.0).abs() < 0.05);
+        assert!(gev.shape.abs() < 0.02);
+
+        // p-values extrapolate past the largest of the maxima
+        let p = gev.survival(40.0);
+        assert!(p > 0.0 && p < 1.0 / n as f64);
+        assert!((gev.survival(10.0) - (1.0 - (-1f64).exp())).abs() < 0.01);
+    }
+
+    #[test]
+    fn constant_maxima_cannot_be_fitted() {
+        assert!(Gev::fit(&[3.0; 10]).is_none());
+        assert!(Gev::fit(&[1.0, 2.0]).is_none());
+    }
+}
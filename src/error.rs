@@ -0,0 +1,172 @@
+use rayon::ThreadPoolBuildError;
+use std::error;
+use std::fmt;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Errors produced when reading genotype and trait files, or when the
+/// inputs to a scan do not match each other.
+///
+/// Line and column numbers are 1-based, as shown by most text editors.
+#[derive(Debug)]
+pub enum Error {
+    /// Opening, reading or writing a file failed
+    Io { path: PathBuf, source: io::Error },
+    /// The genotype file has no `Chr Locus cM` header line, or the
+    /// traits file does not begin with a `Trait` header line
+    MissingHeader { path: PathBuf },
+    /// The `@` properties above the genotype header did not include
+    /// all of `@name`, `@mat`, `@pat` and `@type`
+    MissingMetadata {
+        path: PathBuf,
+        fields: Vec<&'static str>,
+    },
+    /// An `@` property line without a `:` separating name and value
+    IllFormedProperty {
+        path: PathBuf,
+        line: usize,
+        text: String,
+    },
+    /// A genotype that is not one of the `@mat`, `@pat`, `@het` or
+    /// `@unk` codes
+    UnknownGenotype {
+        path: PathBuf,
+        line: usize,
+        column: usize,
+        genotype: String,
+    },
+    /// A cM, Mb or trait value that could not be parsed as a number
+    InvalidNumber {
+        path: PathBuf,
+        line: usize,
+        column: usize,
+        value: String,
+    },
+    /// A row with a different number of columns than its header
+    ColumnCount {
+        path: PathBuf,
+        line: usize,
+        expected: usize,
+        found: usize,
+    },
+    /// A strain in the traits file that is not in the genotype file
+    UnknownStrain { strain: String },
+    /// A strain that was added to the genotypes but is already in them
+    DuplicateStrain { strain: String },
+    /// A marker name that could not be found in the genome
+    UnknownMarker { name: String },
+    /// A strain with a trait value that is not in the covariates file
+    MissingCovariates { strain: String },
+    /// An interactive covariate that is not in the covariates file
+    UnknownCovariate { name: String },
+    /// Weighted regression was requested for a trait without standard
+    /// errors
+    MissingStdErrors { name: String },
+    /// The thread pool of the scans could not be created
+    ThreadPool { source: ThreadPoolBuildError },
+}
+
+impl Error {
+    pub fn io(path: &Path, source: io::Error) -> Error {
+        Error::Io {
+            path: path.to_path_buf(),
+            source,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io { path, source } => {
+                write!(f, "{}: {}", path.display(), source)
+            }
+            Error::MissingHeader { path } => {
+                write!(f, "{}: missing header line", path.display())
+            }
+            Error::MissingMetadata { path, fields } => write!(
+                f,
+                "{}: required metadata was not provided: @{}",
+                path.display(),
+                fields.join(", @")
+            ),
+            Error::IllFormedProperty { path, line, text } => write!(
+                f,
+                "{}:{}: ill-formed property {:?}",
+                path.display(),
+                line,
+                text
+            ),
+            Error::UnknownGenotype {
+                path,
+                line,
+                column,
+                genotype,
+            } => write!(
+                f,
+                "{}:{}:{}: unknown genotype {:?}",
+                path.display(),
+                line,
+                column,
+                genotype
+            ),
+            Error::InvalidNumber {
+                path,
+                line,
+                column,
+                value,
+            } => write!(
+                f,
+                "{}:{}:{}: could not parse {:?} as a number",
+                path.display(),
+                line,
+                column,
+                value
+            ),
+            Error::ColumnCount {
+                path,
+                line,
+                expected,
+                found,
+            } => write!(
+                f,
+                "{}:{}: expected {} columns, found {}",
+                path.display(),
+                line,
+                expected,
+                found
+            ),
+            Error::UnknownStrain { strain } => {
+                write!(f, "strain {:?} is not in the genotype file", strain)
+            }
+            Error::DuplicateStrain { strain } => {
+                write!(f, "strain {:?} is already in the genotype file", strain)
+            }
+            Error::UnknownMarker { name } => {
+                write!(f, "marker {:?} is not in the genotype file", name)
+            }
+            Error::MissingCovariates { strain } => {
+                write!(f, "strain {:?} is not in the covariates file", strain)
+            }
+            Error::UnknownCovariate { name } => {
+                write!(f, "covariate {:?} is not in the covariates file", name)
+            }
+            Error::MissingStdErrors { name } => {
+                write!(f, "trait {:?} has no standard errors", name)
+            }
+            Error::ThreadPool { source } => {
+                write!(f, "could not create the thread pool: {}", source)
+            }
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match self {
+            Error::Io { source, .. } => Some(source),
+            Error::ThreadPool { source } => Some(source),
+            _ => None,
+        }
+    }
+}
@@ -5,5 +5,10 @@ extern crate serde_json;
 #[macro_use(s, azip, array)]
 extern crate ndarray;
 
+pub mod distribution;
+pub mod error;
 pub mod geneobject;
+mod linalg;
 pub mod regression;
+
+pub use crate::error::Error;
@@ -0,0 +1,250 @@
+use ndarray::prelude::*;
+use std::cmp::Ordering;
+
+/// Inverts a square matrix using Gauss-Jordan elimination with partial
+/// pivoting. Returns `None` if the matrix is singular.
+pub fn inverse(matrix: &Array2<f64>) -> Option<Array2<f64>> {
+    let n = matrix.rows();
+    assert_eq!(n, matrix.cols(), "Attempted to invert a non-square matrix");
+
+    // pivots this small relative to the entries of the matrix are
+    // treated as zero
+    let scale = matrix.fold(0.0, |m: f64, x| m.max(x.abs()));
+    let tolerance = scale * (n as f64) * f64::EPSILON;
+
+    let mut a = matrix.clone();
+    let mut inv = Array2::eye(n);
+
+    for col in 0..n {
+        // use the row with the largest magnitude in this column as pivot
+        let pivot = (col..n)
+            .max_by(|&i, &j| {
+                let (x, y) = (a[[i, col]].abs(), a[[j, col]].abs());
+                x.partial_cmp(&y).unwrap_or(Ordering::Equal)
+            })
+            .unwrap();
+
+        if a[[pivot, col]].abs() <= tolerance {
+            return None;
+        }
+
+        if pivot != col {
+            for k in 0..n {
+                a.swap([pivot, k], [col, k]);
+                inv.swap([pivot, k], [col, k]);
+            }
+        }
+
+        let p = a[[col, col]];
+        a.row_mut(col).mapv_inplace(|x| x / p);
+        inv.row_mut(col).mapv_inplace(|x| x / p);
+
+        for row in 0..n {
+            if row != col {
+                let factor = a[[row, col]];
+                if factor != 0.0 {
+                    for k in 0..n {
+                        a[[row, k]] -= factor * a[[col, k]];
+                        inv[[row, k]] -= factor * inv[[col, k]];
+                    }
+                }
+            }
+        }
+    }
+
+    Some(inv)
+}
+
+/// The result of a least squares fit
+pub struct Fit {
+    pub coefficients: Array1<f64>,
+    /// The (weighted) residual sum of squares
+    pub rss: f64,
+    /// The inverse of `X'X` (or `X'WX`), which scaled by the residual
+    /// variance is the covariance matrix of the coefficients
+    pub inverse: Array2<f64>,
+}
+
+/// Fits `y = X b` by (weighted) least squares, solving the normal
+/// equations. `x` has one row per observation, and `weights` are the
+/// inverse variances of the observations. Returns `None` if `X` does
+/// not have full column rank.
+pub fn least_squares(
+    y: &[f64],
+    x: &Array2<f64>,
+    weights: Option<&[f64]>,
+) -> Option<Fit> {
+    least_squares_with(y, x, weights, &mut Array2::zeros((0, 0)))
+}
+
+/// `least_squares`, with the weighted design `WX` of a weighted fit
+/// filled in `wx`, which is reallocated only if it does not have the
+/// shape of `x`
+pub fn least_squares_with(
+    y: &[f64],
+    x: &Array2<f64>,
+    weights: Option<&[f64]>,
+    wx: &mut Array2<f64>,
+) -> Option<Fit> {
+    let y = ArrayView1::from(y);
+
+    let (xtx, xty) = match weights {
+        None => (x.t().dot(x), x.t().dot(&y)),
+        Some(w) => {
+            if wx.dim() != x.dim() {
+                *wx = Array2::zeros(x.dim());
+            }
+            wx.assign(x);
+            *wx *= &ArrayView1::from(w).insert_axis(Axis(1));
+            (wx.t().dot(x), wx.t().dot(&y))
+        }
+    };
+
+    let inverse = inverse(&xtx)?;
+    let coefficients = inverse.dot(&xty);
+
+    let residual = |ix: usize| y[ix] - x.row(ix).dot(&coefficients);
+    let rss = match weights {
+        None => (0..y.len()).map(|ix| residual(ix).powi(2)).sum(),
+        Some(w) => (0..y.len()).map(|ix| w[ix] * residual(ix).powi(2)).sum(),
+    };
+
+    Some(Fit {
+        coefficients,
+        rss,
+        inverse,
+    })
+}
+
+/// Builds a design matrix with an intercept column followed by the
+/// given columns
+pub fn design_matrix(n: usize, columns: &[&[f64]]) -> Array2<f64> {
+    let mut x = Array2::ones((n, columns.len() + 1));
+    fill_design_matrix(&mut x, n, columns);
+    x
+}
+
+/// Fills `x` with the design matrix of `design_matrix`, reallocating it
+/// only if it does not have the shape of the design
+pub fn fill_design_matrix(x: &mut Array2<f64>, n: usize, columns: &[&[f64]]) {
+    if x.dim() == (n, columns.len() + 1) {
+        x.column_mut(0).fill(1.0);
+    } else {
+        *x = Array2::ones((n, columns.len() + 1));
+    }
+    for (ix, column) in columns.iter().enumerate() {
+        x.column_mut(ix + 1).assign(&ArrayView1::from(*column));
+    }
+}
+
+/// Extends the orthonormal `basis` with `columns` by Gram-Schmidt.
+/// Returns `false` if a column is in the span of the basis and the
+/// columns before it, in which case the basis is left incomplete.
+pub fn orthonormalise(
+    basis: &mut Vec<Array1<f64>>,
+    columns: &[&[f64]],
+) -> bool {
+    for column in columns {
+        let mut v = Array1::from_vec(column.to_vec());
+        let norm = v.dot(&v).sqrt();
+
+        // projecting out the basis twice keeps the result orthogonal to
+        // working precision
+        for _ in 0..2 {
+            for b in basis.iter() {
+                let p = b.dot(&v);
+                v.scaled_add(-p, b);
+            }
+        }
+
+        let rest = v.dot(&v).sqrt();
+        if norm == 0.0 || rest <= 1e-10 * norm {
+            return false;
+        }
+        v /= rest;
+        basis.push(v);
+    }
+    true
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn it_can_invert_a_matrix() {
+        let a = array![[4.0, 2.0, 0.6], [2.0, 2.0, 0.4], [0.6, 0.4, 1.0]];
+        let inv = inverse(&a).unwrap();
+
+        assert!(a.dot(&inv).all_close(&Array2::eye(3), 1e-12));
+    }
+
+    #[test]
+    fn singular_matrices_have_no_inverse() {
+        let a = array![[1.0, 2.0], [2.0, 4.0]];
+        assert_eq!(None, inverse(&a));
+    }
+
+    #[test]
+    fn it_can_fit_a_line() {
+        let x = [0.0, 1.0, 2.0, 3.0];
+        let y = [1.0, 3.0, 5.0, 7.0];
+        let fit = least_squares(&y, &design_matrix(4, &[&x]), None).unwrap();
+
+        assert!(fit.coefficients.all_close(&array![1.0, 2.0], 1e-12));
+        assert!(fit.rss.abs() < 1e-12);
+    }
+
+    #[test]
+    fn weighted_fits_reuse_the_weighted_design() {
+        let x = [0.0, 1.0, 2.0, 3.0];
+        let y = [1.2, 2.9, 5.3, 6.8];
+        let w = [1.0, 4.0, 2.0, 0.5];
+
+        // the weighted normal equations, solved directly
+        let (sw, swx, swxx, swy, swxy) = (0..4).fold(
+            (0.0, 0.0, 0.0, 0.0, 0.0),
+            |(sw, swx, swxx, swy, swxy), i| {
+                (
+                    sw + w[i],
+                    swx + w[i] * x[i],
+                    swxx + w[i] * x[i] * x[i],
+                    swy + w[i] * y[i],
+                    swxy + w[i] * x[i] * y[i],
+                )
+            },
+        );
+        let slope = (sw * swxy - swx * swy) / (sw * swxx - swx * swx);
+        let intercept = (swy - slope * swx) / sw;
+
+        // a buffer of another shape is reallocated, and one of the same
+        // shape is overwritten
+        let mut wx = Array2::zeros((2, 5));
+        for _ in 0..2 {
+            let fit = least_squares_with(
+                &y,
+                &design_matrix(4, &[&x]),
+                Some(&w),
+                &mut wx,
+            )
+            .unwrap();
+            assert!(fit
+                .coefficients
+                .all_close(&array![intercept, slope], 1e-12));
+            assert_eq!((4, 2), wx.dim());
+        }
+    }
+
+    #[test]
+    fn it_can_orthonormalise_columns() {
+        let x = [0.0, 1.0, 2.0, 3.0];
+        let mut basis = Vec::new();
+        assert!(orthonormalise(&mut basis, &[&[1.0; 4], &x]));
+
+        assert!((basis[0].dot(&basis[1])).abs() < 1e-12);
+        assert!((basis[1].dot(&basis[1]) - 1.0).abs() < 1e-12);
+
+        let y = [1.0, 3.0, 5.0, 7.0];
+        assert!(!orthonormalise(&mut basis, &[&y]));
+    }
+}
@@ -1,14 +1,18 @@
 extern crate structopt;
 
+use serde::Serialize;
 use std::fs::File;
 use std::io::prelude::*;
 use std::path::PathBuf;
+use std::process;
 use structopt::StructOpt;
 
-use qtlreaper::geneobject::{Dataset, Traits};
-use qtlreaper::regression;
-
-use serde_json;
+use qtlreaper::geneobject::{Covariates, Dataset, Traits, QTL};
+use qtlreaper::regression::{
+    self, Bootstrap, EarlyStop, Model, MultipleQtl, Peak, PeakIntervals,
+    Permutations, ScanTwo, ScanTwoPermutations, Thresholds,
+};
+use qtlreaper::Error;
 
 #[derive(StructOpt, Debug)]
 #[structopt(name = "qtlreaper")]
@@ -19,12 +23,47 @@ struct Opt {
     #[structopt(long = "traits")]
     traits_file: PathBuf,
 
+    #[structopt(
+        long = "se",
+        long_help = r"standard errors file, in the same format as the traits file",
+        parse(from_os_str)
+    )]
+    std_errors_file: Option<PathBuf>,
+
+    #[structopt(
+        long = "variance",
+        long_help = r"weight strains by the inverse of their trait variance, using the standard errors from the traits file (rows named e.g. T1_SE) or from --se"
+    )]
+    variance: bool,
+
     #[structopt(
         short = "c",
         long = "control",
-        long_help = r"control marker name"
+        long_help = r"control marker name; repeat to fit several control markers",
+        number_of_values = 1
     )]
-    control: Option<String>,
+    control: Vec<String>,
+
+    #[structopt(
+        long = "window",
+        long_help = r"leave out control markers within this many cM of the scanned locus"
+    )]
+    window: Option<f64>,
+
+    #[structopt(
+        long = "covariates",
+        long_help = r"covariates file, with one row per strain and one column per covariate; non-numeric columns are treated as categorical",
+        parse(from_os_str)
+    )]
+    covariates_file: Option<PathBuf>,
+
+    #[structopt(
+        short = "i",
+        long = "interactive",
+        long_help = r"covariate that interacts with the QTL; repeat to fit several interactive covariates",
+        number_of_values = 1
+    )]
+    interactive: Vec<String>,
 
     #[structopt(
         short = "o",
@@ -41,6 +80,13 @@ struct Opt {
     )]
     permu_output_file: PathBuf,
 
+    #[structopt(
+        long = "interaction_permu_output",
+        long_help = r"interaction permutations output file, written with --interactive",
+        default_value = "interaction_permutations_output.txt"
+    )]
+    interaction_permu_output_file: PathBuf,
+
     #[structopt(
         short = "n",
         long = "n_permutations",
@@ -49,6 +95,32 @@ struct Opt {
     )]
     n_permutations: usize,
 
+    #[structopt(
+        long = "alpha",
+        long_help = r"genome-wide significance level of a significant LRS threshold to report; repeat for several levels [default: 0.05]",
+        number_of_values = 1
+    )]
+    alphas: Vec<f64>,
+
+    #[structopt(
+        long = "early_stop",
+        long_help = r"stop permuting a trait once --top_n permutation maxima exceed its highest LRS; no thresholds are reported then, and all permutations are run with --stepwise"
+    )]
+    early_stop: bool,
+
+    #[structopt(
+        long = "lrs_threshold",
+        long_help = r"stop permuting a trait once --top_n permutation maxima exceed this LRS; no thresholds are reported then, and all permutations are run with --stepwise"
+    )]
+    lrs_threshold: Option<f64>,
+
+    #[structopt(
+        long = "top_n",
+        long_help = r"number of permutation maxima above the LRS threshold to stop at",
+        default_value = "10"
+    )]
+    top_n: usize,
+
     #[structopt(short = "b", long = "bootstrap", long_help = "run bootstrap")]
     bootstrap: bool,
 
@@ -66,10 +138,136 @@ struct Opt {
     )]
     n_bootstrap: usize,
 
+    #[structopt(
+        long = "scantwo",
+        long_help = r"run a two-dimensional scan of every pair of loci"
+    )]
+    scantwo: bool,
+
+    #[structopt(
+        long = "scantwo_output",
+        long_help = r"two-dimensional scan output file; the lower triangle of each matrix holds the full LRS and the upper triangle the interaction LRS",
+        default_value = "scantwo_output.txt"
+    )]
+    scantwo_output: PathBuf,
+
+    #[structopt(
+        long = "scantwo_permu_output",
+        long_help = r"two-dimensional scan permutations output file, with the full and interaction LRS thresholds of each trait",
+        default_value = "scantwo_permutations_output.txt"
+    )]
+    scantwo_permu_output: PathBuf,
+
+    #[structopt(
+        long = "n_scantwo_permutations",
+        long_help = r"number of permutations of the two-dimensional scan",
+        default_value = "0"
+    )]
+    n_scantwo_permutations: usize,
+
+    #[structopt(
+        long = "stepwise",
+        long_help = r"select a multiple-QTL model by forward selection and backward elimination"
+    )]
+    stepwise: bool,
+
+    #[structopt(
+        long = "stepwise_output",
+        long_help = r"multiple-QTL model output file",
+        default_value = "stepwise_output.txt"
+    )]
+    stepwise_output: PathBuf,
+
+    #[structopt(
+        long = "stepwise_alpha",
+        long_help = r"genome-wide significance level of the permutation threshold that loci must exceed to enter and stay in the model",
+        default_value = "0.05"
+    )]
+    stepwise_alpha: f64,
+
+    #[structopt(
+        long = "max_qtl",
+        long_help = r"maximum number of loci in the multiple-QTL model",
+        default_value = "10"
+    )]
+    max_qtl: usize,
+
+    #[structopt(
+        long = "summary",
+        long_help = r"write the peaks of each trait, with their genome-wide p-values, to the summary output"
+    )]
+    summary: bool,
+
+    #[structopt(
+        long = "summary_output",
+        long_help = r"peak summary output file",
+        default_value = "summary_output.txt"
+    )]
+    summary_output: PathBuf,
+
+    #[structopt(
+        long = "peak_drop",
+        long_help = r"call several peaks on a chromosome when the LOD drops by at least this much between them; otherwise only the highest locus of each chromosome is a peak"
+    )]
+    peak_drop: Option<f64>,
+
+    #[structopt(
+        long = "support_intervals",
+        long_help = r"find the LOD drop and Bayes credible intervals of the peak on each chromosome"
+    )]
+    support_intervals: bool,
+
+    #[structopt(
+        long = "support_intervals_output",
+        long_help = r"support intervals output file",
+        default_value = "support_intervals_output.txt"
+    )]
+    support_intervals_output: PathBuf,
+
+    #[structopt(
+        long = "lod_drop",
+        long_help = r"LOD drop from the peak that bounds the LOD drop interval",
+        default_value = "1.5"
+    )]
+    lod_drop: f64,
+
+    #[structopt(
+        long = "credible",
+        long_help = r"probability of the Bayes credible interval",
+        default_value = "0.95"
+    )]
+    credible: f64,
+
+    #[structopt(
+        long = "epistasis",
+        long_help = r"scan all other loci for interaction with this marker"
+    )]
+    epistasis: Option<String>,
+
+    #[structopt(
+        long = "epistasis_output",
+        long_help = r"interaction with the --epistasis marker output file",
+        default_value = "epistasis_output.txt"
+    )]
+    epistasis_output: PathBuf,
+
+    #[structopt(
+        long = "epistasis_permu_output",
+        long_help = r"interaction with the --epistasis marker permutations output file",
+        default_value = "epistasis_permutations_output.txt"
+    )]
+    epistasis_permu_output: PathBuf,
+
+    #[structopt(
+        long = "seed",
+        long_help = r"seed for the random number generator of the permutations and bootstrap, from which every trait draws its own samples; random if not given"
+    )]
+    seed: Option<u64>,
+
     #[structopt(
         short = "t",
         long = "threads",
-        long_help = r"number of threads to use",
+        long_help = r"number of threads to use; 0 uses one per core",
         default_value = "1"
     )]
     threads: usize,
@@ -80,6 +278,14 @@ struct Opt {
     )]
     output_json: bool,
 
+    #[structopt(
+        long = "parents_f1",
+        long_help = r"add the F1 and the maternal and paternal parental strains, in that order, with fixed genotypes at every locus, so that their trait values are included in mapping",
+        number_of_values = 3,
+        raw(value_names = r#"&["F1", "Mat", "Pat"]"#)
+    )]
+    parents_f1: Vec<String>,
+
     #[structopt(
         long = "interval",
         long_help = r"use interval mapping, with given step size in cM"
@@ -87,25 +293,294 @@ struct Opt {
     interval_map: Option<f64>,
 }
 
-fn format_header(dataset: &Dataset) -> String {
+// the columns of the trait name and the locus, which every per-locus
+// output starts with
+fn format_locus_header(dataset: &Dataset) -> String {
     let mut start = String::from("ID\tLocus\tChr\tcM");
 
     if dataset.has_mb() {
         start += "\tMb";
     }
+
+    start
+}
+
+fn format_header(dataset: &Dataset, interaction: bool) -> String {
+    let mut start = format_locus_header(dataset);
+    start += "\tLRS\tLOD\tAdditive";
+    start += "\tAdditiveSE\tAdditiveT\tAdditiveLower\tAdditiveUpper";
+    if dataset.dominance {
+        start += "\tDominance";
+        start += "\tDominanceSE\tDominanceT\tDominanceLower\tDominanceUpper";
+    }
+    if interaction {
+        start += "\tLRSFull\tLRSInteraction";
+    }
+
+    start += "\tpPointwise\tPctVarExplained\tpValue\tpGEV";
+    if interaction {
+        start += "\tpInteraction";
+    }
+
+    start + "\n"
+}
+
+// a comment line with the genome-wide thresholds of a trait, written
+// above its loci
+fn format_thresholds(
+    name: &str,
+    permu: &Permutations,
+    n_permutations: usize,
+) -> String {
+    let thresholds = match &permu.thresholds {
+        Some(thresholds) => thresholds,
+        None => {
+            return format!(
+                "# {}: no thresholds, permutations stopped early after {} \
+                 of {}\n",
+                name,
+                permu.lrs.len(),
+                n_permutations
+            );
+        }
+    };
+
+    format!("# {}: {}\n", name, format_threshold_levels(thresholds))
+}
+
+// the suggestive and significant LRS thresholds, separated by semicolons
+fn format_threshold_levels(thresholds: &Thresholds) -> String {
+    let mut out = format!(
+        "suggestive LRS {:.*} (p = {})",
+        3, thresholds.suggestive.lrs, thresholds.suggestive.alpha
+    );
+    for t in thresholds.significant.iter() {
+        out += &format!("; significant LRS {:.*} (p = {})", 3, t.lrs, t.alpha);
+    }
+    out
+}
+
+fn format_stepwise_header(dataset: &Dataset) -> String {
+    let mut start = format_locus_header(dataset);
     start += "\tLRS\tAdditive";
     if dataset.dominance {
         start += "\tDominance";
     }
 
-    start + "\tpValue\n"
+    start + "\tPctVarExplained\n"
 }
 
-fn main() {
-    let opt = Opt::from_args();
+// one line per locus of the model, followed by a line for the whole model
+fn format_stepwise(
+    name: &str,
+    dataset: &Dataset,
+    mqtl: &MultipleQtl,
+) -> String {
+    let mut out = String::new();
+    for qtl in mqtl.qtls.iter() {
+        out += &format!("{}\t{}\n", name, qtl);
+    }
+
+    out += &format!("{}\tmodel\t\t", name);
+    if dataset.has_mb() {
+        out += "\t";
+    }
+    out += &format!("\t{:.*}\t", 3, mqtl.lrs);
+    if dataset.dominance {
+        out += "\t";
+    }
+    out + &format!("\t{:.*}\n", 3, mqtl.variance_explained)
+}
+
+// a JSON record of the output of a trait, with the name of the trait as
+// its first field
+#[derive(Serialize)]
+struct TraitRecord<'a, T> {
+    #[serde(rename = "trait")]
+    name: &'a str,
+    #[serde(flatten)]
+    record: &'a T,
+}
+
+// the JSON record of `record`, with the name of the trait
+fn trait_record_json<T: Serialize>(name: &str, record: &T) -> String {
+    serde_json::to_string(&TraitRecord { name, record })
+        .expect("Error generating JSON")
+}
+
+// the JSON records of `records`, each with the name of the trait
+fn trait_records_json<T: Serialize>(name: &str, records: &[T]) -> String {
+    let records: Vec<_> = records
+        .iter()
+        .map(|record| TraitRecord { name, record })
+        .collect();
+    serde_json::to_string(&records).expect("Error generating JSON")
+}
+
+// the main output of a trait: its genome-wide thresholds and the scan
+#[derive(Serialize)]
+struct ScanRecord<'a> {
+    #[serde(flatten)]
+    permutations: &'a Permutations,
+    qtls: &'a [QTL],
+}
+
+// the maximum LRS of each permutation of a trait
+#[derive(Serialize)]
+struct PermutationsRecord<'a> {
+    lrs: &'a [f64],
+}
+
+fn format_summary_header(dataset: &Dataset) -> String {
+    format_locus_header(dataset) + "\tLRS\tAdditive\tpValue\tpGEV\n"
+}
+
+fn format_support_intervals_header(dataset: &Dataset) -> String {
+    let mut start = format_locus_header(dataset);
+    start += "\tLOD";
+    for interval in ["Drop", "Bayes"].iter() {
+        start += &format!("\t{0}Start\t{0}End\t{0}StartcM\t{0}EndcM", interval);
+        if dataset.has_mb() {
+            start += &format!("\t{0}StartMb\t{0}EndMb", interval);
+        }
+    }
+
+    start + "\n"
+}
+
+// one line per chromosome, with the peak and its intervals
+fn format_support_intervals(name: &str, peaks: &[PeakIntervals]) -> String {
+    let mut out = String::new();
+    for peak in peaks.iter() {
+        out += &format!("{}\t{}\t{:.*}", name, peak.peak, 3, peak.lod);
+
+        for interval in [&peak.lod_drop, &peak.bayes].iter() {
+            let (start, end) = (&interval.start, &interval.end);
+            out += &format!(
+                "\t{}\t{}\t{:.*}\t{:.*}",
+                start.name,
+                end.name,
+                3,
+                start.centi_morgan,
+                3,
+                end.centi_morgan
+            );
+            if let (Some(start), Some(end)) =
+                (start.mega_basepair, end.mega_basepair)
+            {
+                out += &format!("\t{:.*}\t{:.*}", 3, start, 3, end);
+            }
+        }
+        out += "\n";
+    }
+    out
+}
+
+fn format_bootstrap_header(dataset: &Dataset) -> String {
+    format_locus_header(dataset) + "\tCount\n"
+}
+
+// a comment line with the bootstrap interval of the trait, followed by
+// the number of samples that peak at each locus
+fn format_bootstrap(name: &str, bootstrap: &Bootstrap) -> String {
+    let mut out = format!("# {}: {} samples", name, bootstrap.n_samples);
+    if let Some(interval) = &bootstrap.interval {
+        out += &format!(
+            "; 95% interval chr {}, {} - {} ({:.*} - {:.*} cM",
+            interval.chromosome,
+            interval.start.name,
+            interval.end.name,
+            3,
+            interval.start.centi_morgan,
+            3,
+            interval.end.centi_morgan
+        );
+        if let (Some(start), Some(end)) =
+            (interval.start.mega_basepair, interval.end.mega_basepair)
+        {
+            out += &format!(", {:.*} - {:.*} Mb", 3, start, 3, end);
+        }
+        out += ")";
+    }
+    out += "\n";
+
+    for (marker, count) in bootstrap.counts.iter() {
+        out += &format!("{}\t{}\t{}\n", name, marker, count);
+    }
+
+    out
+}
+
+fn format_epistasis_header(dataset: &Dataset) -> String {
+    let mut start = format_locus_header(dataset);
+    start += "\tLRSInteraction\tAdditive";
+    if dataset.dominance {
+        start += "\tDominance";
+    }
+
+    start + "\tInteraction\tpValue\n"
+}
+
+// comment lines with the full and interaction thresholds of a trait,
+// followed by a row per permutation
+fn format_scantwo_permutations(
+    name: &str,
+    permu: &ScanTwoPermutations,
+) -> String {
+    if permu.full.is_empty() {
+        return String::new();
+    }
+
+    let mut out = format!(
+        "# {} full: {}\n# {} interaction: {}\n",
+        name,
+        format_threshold_levels(&permu.full_thresholds),
+        name,
+        format_threshold_levels(&permu.interaction_thresholds)
+    );
+    for (full, int) in permu.full.iter().zip(&permu.interaction) {
+        out += &format!("{:.*}\t{:.*}\n", 5, full, 5, int);
+    }
+    out
+}
+
+// the full LRS below the diagonal and the interaction LRS above it, with
+// a row per locus
+fn format_scantwo(name: &str, scan: &ScanTwo) -> String {
+    let mut out = String::from("ID\tLocus");
+    for marker in scan.markers.iter() {
+        out += "\t";
+        out += &marker.name;
+    }
+    out += "\n";
+
+    for (i, marker) in scan.markers.iter().enumerate() {
+        out += &format!("{}\t{}", name, marker.name);
+        for j in 0..scan.markers.len() {
+            let lrs = if i > j {
+                scan.lrs_full[i][j]
+            } else {
+                scan.lrs_interaction[i][j]
+            };
+            out += &format!("\t{:.*}", 3, lrs);
+        }
+        out += "\n";
+    }
+
+    out
+}
+
+fn run(opt: Opt) -> Result<(), Error> {
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(opt.threads)
+        .build_global()
+        .map_err(|source| Error::ThreadPool { source })?;
 
     let dataset = {
-        let d = Dataset::read_file(&opt.genotype_file);
+        let mut d = Dataset::read_file(&opt.genotype_file)?;
+        if let [f1, mat, pat] = &opt.parents_f1[..] {
+            d.add_parents_f1(f1, mat, pat)?;
+        }
         if let Some(iv) = opt.interval_map {
             d.interval_mapped_clone(iv)
         } else {
@@ -113,88 +588,423 @@ fn main() {
         }
     };
 
-    let traits = Traits::read_file(&opt.traits_file);
+    let mut traits = Traits::read_file(&opt.traits_file)?;
+    if let Some(path) = &opt.std_errors_file {
+        traits.read_std_errors(path)?;
+    }
+
+    let std_errors = |name: &str| {
+        if opt.variance {
+            traits.std_errors(name).map(Some).ok_or_else(|| {
+                Error::MissingStdErrors {
+                    name: name.to_string(),
+                }
+            })
+        } else {
+            Ok(None)
+        }
+    };
+
+    let output_err = |e| Error::io(&opt.output_file, e);
+    let permu_err = |e| Error::io(&opt.permu_output_file, e);
+
+    let mut fout = File::create(&opt.output_file).map_err(output_err)?;
+
+    let mut permu_fout =
+        File::create(&opt.permu_output_file).map_err(permu_err)?;
+
+    let interaction = !opt.interactive.is_empty();
+    let interaction_permu_err =
+        |e| Error::io(&opt.interaction_permu_output_file, e);
+    let mut interaction_permu_fout = if interaction {
+        Some(
+            File::create(&opt.interaction_permu_output_file)
+                .map_err(interaction_permu_err)?,
+        )
+    } else {
+        None
+    };
+
+    let summary_err = |e| Error::io(&opt.summary_output, e);
+    let mut summary_fout = if opt.summary {
+        Some(File::create(&opt.summary_output).map_err(summary_err)?)
+    } else {
+        None
+    };
 
-    let mut fout =
-        File::create(opt.output_file).expect("Error creating output file");
+    let support_intervals_err = |e| Error::io(&opt.support_intervals_output, e);
+    let mut support_intervals_fout = if opt.support_intervals {
+        Some(
+            File::create(&opt.support_intervals_output)
+                .map_err(support_intervals_err)?,
+        )
+    } else {
+        None
+    };
 
-    let mut permu_fout = File::create(opt.permu_output_file)
-        .expect("Error creating permutations output file");
+    let stepwise_err = |e| Error::io(&opt.stepwise_output, e);
+    let mut stepwise_fout = if opt.stepwise {
+        Some(File::create(&opt.stepwise_output).map_err(stepwise_err)?)
+    } else {
+        None
+    };
 
     if !opt.output_json {
-        fout.write_all(format_header(&dataset).as_bytes())
-            .expect("Error writing JSON output");
+        fout.write_all(format_header(&dataset, interaction).as_bytes())
+            .map_err(output_err)?;
+
+        if let Some(f) = &mut stepwise_fout {
+            f.write_all(format_stepwise_header(&dataset).as_bytes())
+                .map_err(stepwise_err)?;
+        }
+
+        if let Some(f) = &mut support_intervals_fout {
+            f.write_all(format_support_intervals_header(&dataset).as_bytes())
+                .map_err(support_intervals_err)?;
+        }
+
+        if let Some(f) = &mut summary_fout {
+            f.write_all(format_summary_header(&dataset).as_bytes())
+                .map_err(summary_err)?;
+        }
     }
 
-    for (name, values) in traits.traits.iter() {
+    let seed = opt.seed.unwrap_or_else(rand::random);
+    let alphas = if opt.alphas.is_empty() {
+        vec![0.05]
+    } else {
+        opt.alphas.clone()
+    };
+
+    let covariates = match &opt.covariates_file {
+        Some(path) => Some(Covariates::read_file(path)?),
+        None => None,
+    };
+
+    let model = Model {
+        control: opt.control.clone(),
+        window: opt.window,
+        covariates,
+        interactive: opt.interactive.clone(),
+    };
+
+    for (trait_ix, (name, values)) in traits.traits.iter().enumerate() {
+        let seed = regression::trait_seed(seed, trait_ix);
+        let std_errors = std_errors(name)?;
         let qtls = regression::regression(
             &dataset,
             values,
+            std_errors,
             &traits.strains,
-            opt.control.as_ref().map(|s| &**s),
-        );
+            &model,
+        )?;
+        // the stepwise threshold needs all the permutations
+        let early_stop = if (opt.early_stop || opt.lrs_threshold.is_some())
+            && !opt.stepwise
+        {
+            let lrs = opt.lrs_threshold.unwrap_or_else(|| {
+                qtls.iter().map(|qtl| qtl.lrs).fold(0.0, f64::max)
+            });
+            Some(EarlyStop {
+                lrs,
+                top_n: opt.top_n,
+            })
+        } else {
+            None
+        };
         let permu = regression::permutation(
             &dataset,
             values,
+            std_errors,
             &traits.strains,
+            &model,
             opt.n_permutations,
-            opt.threads,
-        );
+            seed,
+            early_stop,
+            &alphas,
+        )?;
+        let interaction_permu = if interaction {
+            regression::interaction_permutation(
+                &dataset,
+                values,
+                std_errors,
+                &traits.strains,
+                &model,
+                opt.n_permutations,
+                seed,
+            )?
+        } else {
+            Vec::new()
+        };
 
         if opt.output_json {
-            for qtl in qtls.iter() {
+            let scan = ScanRecord {
+                permutations: &permu,
+                qtls: &qtls,
+            };
+            fout.write_all(trait_record_json(name, &scan).as_bytes())
+                .map_err(output_err)?;
+
+            let lrs = PermutationsRecord { lrs: &permu.lrs };
+            permu_fout
+                .write_all(trait_record_json(name, &lrs).as_bytes())
+                .map_err(permu_err)?;
+
+            if let Some(f) = &mut interaction_permu_fout {
+                let lrs = PermutationsRecord {
+                    lrs: &interaction_permu,
+                };
+                f.write_all(trait_record_json(name, &lrs).as_bytes())
+                    .map_err(interaction_permu_err)?;
+            }
+        } else {
+            if !permu.lrs.is_empty() {
                 fout.write_all(
-                    serde_json::to_string(qtl)
-                        .expect("Error generating JSON")
+                    format_thresholds(name, &permu, opt.n_permutations)
                         .as_bytes(),
                 )
-                .expect("Error writing JSON output");
+                .map_err(output_err)?;
             }
 
-            permu_fout
-                .write_all(
-                    serde_json::to_string(&permu)
-                        .expect("Error generating permutations JSON")
-                        .as_bytes(),
-                )
-                .expect("Error writing JSON permutations output");
-        } else {
             for qtl in qtls.iter() {
-                let pvalue = regression::pvalue(qtl.lrs, &permu);
-
-                let line = format!("{}\t{}\t{:.*}\n", name, qtl, 3, pvalue);
-
-                fout.write_all(line.as_bytes())
-                    .expect("Error writing output");
+                let pvalue = regression::pvalue(qtl.lrs, &permu.lrs);
+
+                let mut line = format!("{}\t{}\t{:.*}", name, qtl, 3, pvalue);
+                // extrapolated p-values can be far below 0.001
+                match permu.pvalue_gev(qtl.lrs) {
+                    Some(p) => line += &format!("\t{:.*e}", 3, p),
+                    None => line += "\tNA",
+                }
+                if let Some(lrs) = qtl.lrs_interaction {
+                    let pvalue = regression::pvalue(lrs, &interaction_permu);
+                    line += &format!("\t{:.*}", 3, pvalue);
+                }
+                line += "\n";
+
+                fout.write_all(line.as_bytes()).map_err(output_err)?;
             }
 
-            for p in permu.iter() {
+            for p in permu.lrs.iter() {
                 permu_fout
                     .write_all(format!("{:.*}\n", 5, p).as_bytes())
-                    .expect("Error writing permutations output");
+                    .map_err(permu_err)?;
+            }
+
+            if let Some(f) = &mut interaction_permu_fout {
+                for p in interaction_permu.iter() {
+                    f.write_all(format!("{:.*}\n", 5, p).as_bytes())
+                        .map_err(interaction_permu_err)?;
+                }
             }
         }
+
+        if let Some(f) = &mut summary_fout {
+            let peaks: Vec<_> = regression::peaks(&qtls, opt.peak_drop)
+                .into_iter()
+                .map(|qtl| Peak::new(qtl, &permu))
+                .collect();
+
+            let out = if opt.output_json {
+                trait_records_json(name, &peaks)
+            } else {
+                peaks
+                    .iter()
+                    .map(|peak| format!("{}\t{}\n", name, peak))
+                    .collect()
+            };
+            f.write_all(out.as_bytes()).map_err(summary_err)?;
+        }
+
+        if let Some(f) = &mut support_intervals_fout {
+            let peaks = regression::support_intervals(
+                &qtls,
+                opt.lod_drop,
+                opt.credible,
+            );
+
+            let out = if opt.output_json {
+                trait_records_json(name, &peaks)
+            } else {
+                format_support_intervals(name, &peaks)
+            };
+            f.write_all(out.as_bytes()).map_err(support_intervals_err)?;
+        }
+
+        if let Some(f) = &mut stepwise_fout {
+            let mqtl = regression::stepwise(
+                &dataset,
+                values,
+                std_errors,
+                &traits.strains,
+                &model,
+                regression::threshold(&permu.lrs, opt.stepwise_alpha),
+                opt.max_qtl,
+            )?;
+
+            let out = if opt.output_json {
+                trait_record_json(name, &mqtl)
+            } else {
+                format_stepwise(name, &dataset, &mqtl)
+            };
+            f.write_all(out.as_bytes()).map_err(stepwise_err)?;
+        }
     }
 
     if opt.bootstrap {
-        let mut bootstrap_fout = File::create(opt.bootstrap_output)
-            .expect("Error creating bootstrap output file");
+        let bootstrap_err = |e| Error::io(&opt.bootstrap_output, e);
+        let mut bootstrap_fout =
+            File::create(&opt.bootstrap_output).map_err(bootstrap_err)?;
+
+        if !opt.output_json {
+            bootstrap_fout
+                .write_all(format_bootstrap_header(&dataset).as_bytes())
+                .map_err(bootstrap_err)?;
+        }
 
-        for (_name, values) in traits.traits.iter() {
+        for (trait_ix, (name, values)) in traits.traits.iter().enumerate() {
+            let seed = regression::trait_seed(seed, trait_ix);
             let bootstrap = regression::bootstrap(
                 &dataset,
                 values,
+                std_errors(name)?,
                 &traits.strains,
-                None,
-                1000,
-            );
+                &model,
+                opt.n_bootstrap,
+                seed,
+            )?;
+
+            let out = if opt.output_json {
+                trait_record_json(name, &bootstrap)
+            } else {
+                format_bootstrap(name, &bootstrap)
+            };
+            bootstrap_fout
+                .write_all(out.as_bytes())
+                .map_err(bootstrap_err)?;
+        }
+    }
+
+    if let Some(fixed) = &opt.epistasis {
+        let epistasis_err = |e| Error::io(&opt.epistasis_output, e);
+        let epistasis_permu_err = |e| Error::io(&opt.epistasis_permu_output, e);
+        let mut epistasis_fout =
+            File::create(&opt.epistasis_output).map_err(epistasis_err)?;
+        let mut epistasis_permu_fout =
+            File::create(&opt.epistasis_permu_output)
+                .map_err(epistasis_permu_err)?;
+
+        if !opt.output_json {
+            epistasis_fout
+                .write_all(format_epistasis_header(&dataset).as_bytes())
+                .map_err(epistasis_err)?;
+        }
+
+        for (trait_ix, (name, values)) in traits.traits.iter().enumerate() {
+            let seed = regression::trait_seed(seed, trait_ix);
+            let std_errors = std_errors(name)?;
+            let scan = regression::epistasis(
+                &dataset,
+                values,
+                std_errors,
+                &traits.strains,
+                &model,
+                fixed,
+            )?;
+            let permu = regression::epistasis_permutation(
+                &dataset,
+                values,
+                std_errors,
+                &traits.strains,
+                &model,
+                fixed,
+                opt.n_permutations,
+                seed,
+            )?;
+
+            if opt.output_json {
+                epistasis_fout
+                    .write_all(trait_records_json(name, &scan).as_bytes())
+                    .map_err(epistasis_err)?;
+                let lrs = PermutationsRecord { lrs: &permu };
+                epistasis_permu_fout
+                    .write_all(trait_record_json(name, &lrs).as_bytes())
+                    .map_err(epistasis_permu_err)?;
+            } else {
+                for result in scan.iter() {
+                    let pvalue = regression::pvalue(result.lrs, &permu);
+                    let line =
+                        format!("{}\t{}\t{:.*}\n", name, result, 3, pvalue);
+                    epistasis_fout
+                        .write_all(line.as_bytes())
+                        .map_err(epistasis_err)?;
+                }
+
+                for p in permu.iter() {
+                    epistasis_permu_fout
+                        .write_all(format!("{:.*}\n", 5, p).as_bytes())
+                        .map_err(epistasis_permu_err)?;
+                }
+            }
+        }
+    }
 
-            for bs_line in bootstrap.iter() {
-                let line = format!("{}\n", bs_line);
-                bootstrap_fout
-                    .write_all(line.as_bytes())
-                    .expect("Error writing bootstrap output");
+    if opt.scantwo {
+        let scantwo_err = |e| Error::io(&opt.scantwo_output, e);
+        let scantwo_permu_err = |e| Error::io(&opt.scantwo_permu_output, e);
+        let mut scantwo_fout =
+            File::create(&opt.scantwo_output).map_err(scantwo_err)?;
+        let mut scantwo_permu_fout = File::create(&opt.scantwo_permu_output)
+            .map_err(scantwo_permu_err)?;
+
+        for (trait_ix, (name, values)) in traits.traits.iter().enumerate() {
+            let seed = regression::trait_seed(seed, trait_ix);
+            let std_errors = std_errors(name)?;
+            let scan = regression::scantwo(
+                &dataset,
+                values,
+                std_errors,
+                &traits.strains,
+                &model,
+            )?;
+            let permu = regression::scantwo_permutation(
+                &dataset,
+                values,
+                std_errors,
+                &traits.strains,
+                &model,
+                opt.n_scantwo_permutations,
+                seed,
+                &alphas,
+            )?;
+
+            if opt.output_json {
+                scantwo_fout
+                    .write_all(trait_record_json(name, &scan).as_bytes())
+                    .map_err(scantwo_err)?;
+                scantwo_permu_fout
+                    .write_all(trait_record_json(name, &permu).as_bytes())
+                    .map_err(scantwo_permu_err)?;
+            } else {
+                scantwo_fout
+                    .write_all(format_scantwo(name, &scan).as_bytes())
+                    .map_err(scantwo_err)?;
+                scantwo_permu_fout
+                    .write_all(
+                        format_scantwo_permutations(name, &permu).as_bytes(),
+                    )
+                    .map_err(scantwo_permu_err)?;
             }
         }
     }
+
+    Ok(())
+}
+
+fn main() {
+    let opt = Opt::from_args();
+
+    if let Err(e) = run(opt) {
+        eprintln!("qtlreaper: {}", e);
+        process::exit(1);
+    }
 }
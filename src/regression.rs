@@ -1,28 +1,82 @@
-use crate::geneobject::{Dataset, QTL};
-use rand::Rng;
+use crate::distribution::Gev;
+use crate::error::Error;
+use crate::geneobject::{Covariates, Dataset, EffectStats, Locus, Marker, QTL};
+use crate::linalg;
+use ndarray::prelude::*;
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
 use rayon::prelude::*;
-
-const PERMUTATION_TESTSIZE: usize = 1000;
-const BOOTSTRAP_TESTSIZE: usize = 1000;
-const MAXPERMUTATION: usize = 1000000;
+use serde::Serialize;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::Range;
 
 pub struct RegResult {
     lrs: f64,
     additive: f64,
     dominance: Option<f64>,
+    /// Standard errors of the effects, NaN if they cannot be estimated
+    additive_se: f64,
+    dominance_se: Option<f64>,
+    /// Residual degrees of freedom of the full model
+    df: f64,
+}
+
+// the standard error of a coefficient from the residual sum of squares,
+// the residual degrees of freedom and the coefficient's diagonal element
+// of the inverse of X'X (or X'WX)
+fn std_error(rss: f64, df: f64, inverse_diagonal: f64) -> f64 {
+    (rss / df * inverse_diagonal).sqrt()
+}
+
+// what a random stream is drawn for, so that the permutations of one
+// scan are unrelated to the permutations or bootstrap samples of another
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Domain {
+    Permutation = 1,
+    InteractionPermutation,
+    ScantwoPermutation,
+    EpistasisPermutation,
+    Bootstrap,
+}
+
+// the SplitMix64 finalizer, so that related keys get unrelated seeds
+fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// The seed of the `trait_ix`th trait, so that every trait gets its own
+/// permutations and bootstrap samples from a single `--seed`.
+pub fn trait_seed(seed: u64, trait_ix: usize) -> u64 {
+    mix(seed
+        .wrapping_add((trait_ix as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15)))
+}
+
+// the random number generator of the `stream`th permutation or bootstrap
+// sample of `domain`, so that every sample is the same however the
+// samples are split between threads
+fn stream_rng(seed: u64, domain: Domain, stream: usize) -> StdRng {
+    let key = mix(seed ^ (domain as u64).wrapping_mul(0xd6e8_feb8_6659_fd93));
+    StdRng::seed_from_u64(mix(
+        key.wrapping_add((stream as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15))
+    ))
 }
 
-fn permuted_mut<T>(data: &mut [T]) {
-    let n = data.len();
-    for ix in 0..n {
-        let j = rand::thread_rng().gen_range(0, n);
-        data.swap(ix, j);
+// shuffles `order` from the identity, so that the permutation only
+// depends on `rng`
+fn permuted_mut<R: Rng>(order: &mut [usize], rng: &mut R) {
+    for (ix, o) in order.iter_mut().enumerate() {
+        *o = ix;
     }
+    order.shuffle(rng);
 }
 
-fn bootstrap_indices<T>(v: &[T]) -> Vec<usize> {
+fn bootstrap_indices<T, R: Rng>(v: &[T], rng: &mut R) -> Vec<usize> {
     let n = v.len();
-    (0..n).map(|_| rand::thread_rng().gen_range(0, n)).collect()
+    (0..n).map(|_| rng.gen_range(0, n)).collect()
 }
 
 pub fn pvalue(lrs: f64, permutations: &[f64]) -> f64 {
@@ -33,169 +87,1981 @@ pub fn pvalue(lrs: f64, permutations: &[f64]) -> f64 {
     let n = permutations.len();
 
     // clamp output in [0.0, 1.0] in case of NaN (should never happen)
-    (1.0 - ((i as f64) / (n as f64))).max(0.0).min(1.0)
+    (1.0 - ((i as f64) / (n as f64))).clamp(0.0, 1.0)
+}
+
+/// The cofactors fitted alongside each locus in a scan
+#[derive(Clone, Debug, Default)]
+pub struct Model {
+    /// Names of control markers, fitted as cofactors at every locus
+    pub control: Vec<String>,
+    /// If set, control markers on the same chromosome as the scanned
+    /// locus and within this many cM of it are left out of its model
+    pub window: Option<f64>,
+    /// Additive covariates, fitted in both the null and the full model;
+    /// strains without covariate values are left out of the scan
+    pub covariates: Option<Covariates>,
+    /// Names of covariates that interact with the scanned locus; they
+    /// must also be in `covariates`
+    pub interactive: Vec<String>,
+}
+
+impl Model {
+    // the indices of the covariate columns that interact with the locus;
+    // a categorical covariate has one column per dummy variable
+    fn interactive_columns(&self) -> Result<Vec<usize>, Error> {
+        let mut columns = Vec::new();
+        for name in self.interactive.iter() {
+            let ixs = self
+                .covariates
+                .as_ref()
+                .map(|c| c.column_indices(name))
+                .unwrap_or_default();
+            if ixs.is_empty() {
+                return Err(Error::UnknownCovariate {
+                    name: name.to_string(),
+                });
+            }
+            columns.extend(ixs);
+        }
+        Ok(columns)
+    }
 }
 
-// TODO: add support for variance and control
 // TODO: add support for providing a list of strain names to include
 pub fn regression(
     dataset: &Dataset,
+    traits: &[Option<f64>],
+    std_errors: Option<&[Option<f64>]>,
+    strains: &[String],
+    model: &Model,
+) -> Result<Vec<QTL>, Error> {
+    let (observed, cofactors) =
+        Observed::with_cofactors(dataset, traits, std_errors, strains, model)?;
+    let traits = &observed.traits[..];
+    let weights = observed.weights();
+    let strain_ixs = &observed.strain_ixs;
+
+    let interactive = model.interactive_columns()?;
+    let interactive: Vec<_> = interactive
+        .iter()
+        .map(|ix| &observed.covariates[*ix][..])
+        .collect();
+
+    let n = traits.len();
+    let tss =
+        linalg::least_squares(traits, &linalg::design_matrix(n, &[]), weights)
+            .map_or(0.0, |fit| fit.rss);
+
+    let loci: Vec<_> = dataset.genome.chromosomes.values().flatten().collect();
+
+    let result = loci
+        .par_iter()
+        .map(|locus| {
+            let genotypes = locus.genotypes_subset(strain_ixs);
+            let dominance = if dataset.dominance {
+                Some(locus.dominance_subset(strain_ixs))
+            } else {
+                None
+            };
+
+            let mut columns = cofactors.columns(&[&locus.marker]);
+            columns.extend(observed.covariates.iter().map(|c| &c[..]));
+
+            let dominance = dominance.as_ref().map(|d| &d[..]);
+
+            let reg_result = locus_regression(
+                traits, &genotypes, dominance, &columns, weights,
+            );
+
+            let lrs_full = if interactive.is_empty() {
+                None
+            } else {
+                Some(regression_interaction(
+                    traits,
+                    &genotypes,
+                    dominance,
+                    &columns,
+                    &interactive,
+                    weights,
+                ))
+            };
+
+            // the RSS of the model without the locus, from which the
+            // locus explains a fraction 1 - exp(-LRS / n)
+            let rss0 = if columns.is_empty() {
+                tss
+            } else {
+                linalg::least_squares(
+                    traits,
+                    &linalg::design_matrix(n, &columns),
+                    weights,
+                )
+                .map_or(tss, |fit| fit.rss)
+            };
+            let variance_explained = if tss > 0.0 {
+                100.0 * rss0 / tss * -(-reg_result.lrs / n as f64).exp_m1()
+            } else {
+                0.0
+            };
+
+            let qtl = QTL {
+                lrs_full,
+                lrs_interaction: lrs_full
+                    .map(|lrs| (lrs - reg_result.lrs).max(0.0)),
+                ..QTL::new(
+                    locus.marker.clone(),
+                    reg_result.lrs,
+                    reg_result.additive,
+                    reg_result.dominance,
+                    variance_explained,
+                )
+            };
+            (qtl, reg_result)
+        })
+        .collect::<Vec<_>>();
+
+    // the residual degrees of freedom only change with the number of
+    // cofactors in the window of a locus, so there are few critical
+    // values to compute
+    let mut critical_ts: BTreeMap<i64, Option<f64>> = BTreeMap::new();
+    let qtls = result
+        .into_iter()
+        .map(|(qtl, reg_result)| {
+            let critical_t = *critical_ts
+                .entry(reg_result.df as i64)
+                .or_insert_with(|| EffectStats::critical_t(reg_result.df));
+            let stats = |estimate, std_error| {
+                critical_t
+                    .and_then(|t| EffectStats::new(estimate, std_error, t))
+            };
+
+            QTL {
+                additive_stats: stats(
+                    reg_result.additive,
+                    reg_result.additive_se,
+                ),
+                dominance_stats: reg_result
+                    .dominance
+                    .zip(reg_result.dominance_se)
+                    .and_then(|(d, se)| stats(d, se)),
+                ..qtl
+            }
+        })
+        .collect();
+
+    Ok(qtls)
+}
+
+/// Stops permuting once `top_n` permutation maxima exceed `lrs`. The
+/// p-value of `lrs` is then about `top_n` over the number of permutations,
+/// which is precise enough for LRS that are far from significant.
+#[derive(Clone, Copy, Debug)]
+pub struct EarlyStop {
+    pub lrs: f64,
+    pub top_n: usize,
+}
+
+/// The genome-wide significance level of the suggestive LRS threshold
+pub const SUGGESTIVE_ALPHA: f64 = 0.63;
+
+/// The LRS that a genome-wide maximum exceeds with probability `alpha`
+#[derive(Clone, Copy, Debug, Serialize)]
+pub struct Threshold {
+    pub alpha: f64,
+    pub lrs: f64,
+}
+
+/// Genome-wide LRS thresholds from a permutation distribution
+#[derive(Clone, Debug, Serialize)]
+pub struct Thresholds {
+    pub suggestive: Threshold,
+    /// One threshold per requested significance level
+    pub significant: Vec<Threshold>,
+}
+
+impl Thresholds {
+    /// Thresholds at `SUGGESTIVE_ALPHA` and at each of `alphas`, from the
+    /// sorted `permutations`
+    pub fn new(permutations: &[f64], alphas: &[f64]) -> Thresholds {
+        let at = |alpha| Threshold {
+            alpha,
+            lrs: threshold(permutations, alpha),
+        };
+        Thresholds {
+            suggestive: at(SUGGESTIVE_ALPHA),
+            significant: alphas.iter().map(|&alpha| at(alpha)).collect(),
+        }
+    }
+}
+
+/// The sorted permutation maxima of a scan, the thresholds they give and
+/// the extreme value distribution fitted to them
+#[derive(Clone, Debug, Serialize)]
+pub struct Permutations {
+    /// Written to the permutations output on their own
+    #[serde(skip)]
+    pub lrs: Vec<f64>,
+    /// `None` if the permutations were stopped early
+    pub thresholds: Option<Thresholds>,
+    /// `None` if the permutations were stopped early or the maxima could
+    /// not be fitted
+    pub gev: Option<Gev>,
+}
+
+impl Permutations {
+    /// The genome-wide p-value of `lrs` from the fitted distribution of
+    /// the maxima, which unlike `pvalue` can be below one over the number
+    /// of permutations
+    pub fn pvalue_gev(&self, lrs: f64) -> Option<f64> {
+        self.gev.map(|gev| gev.survival(lrs))
+    }
+}
+
+/// Permutation distribution of the maximum LRS, scanned with the same
+/// model as `regression`, with thresholds at the significance levels
+/// `alphas`
+#[allow(clippy::too_many_arguments)]
+pub fn permutation(
+    dataset: &Dataset,
+    traits: &[Option<f64>],
+    std_errors: Option<&[Option<f64>]>,
+    strains: &[String],
+    model: &Model,
+    n_perms: usize,
+    seed: u64,
+    early_stop: Option<EarlyStop>,
+    alphas: &[f64],
+) -> Result<Permutations, Error> {
+    let lrs = permutation_lrs(
+        dataset, traits, std_errors, strains, model, n_perms, seed, early_stop,
+    )?;
+    // permutations stopped early keep the maxima up to the `top_n`th one
+    // above the threshold, which are biased towards high LRS, so they
+    // only give p-values of LRS near that threshold
+    let (thresholds, gev) = if lrs.len() < n_perms {
+        (None, None)
+    } else {
+        (Some(Thresholds::new(&lrs, alphas)), Gev::fit(&lrs))
+    };
+    Ok(Permutations {
+        lrs,
+        thresholds,
+        gev,
+    })
+}
+
+#[allow(clippy::too_many_arguments)]
+fn permutation_lrs(
+    dataset: &Dataset,
+    traits: &[Option<f64>],
+    std_errors: Option<&[Option<f64>]>,
+    strains: &[String],
+    model: &Model,
+    n_perms: usize,
+    seed: u64,
+    early_stop: Option<EarlyStop>,
+) -> Result<Vec<f64>, Error> {
+    let covariates = model.covariates.as_ref().map_or(0, |c| c.columns.len());
+    if std_errors.is_none() && covariates == 0 {
+        return batched_permutation_maxima(
+            dataset, traits, strains, model, n_perms, seed, early_stop,
+        );
+    }
+
+    permutation_maxima(
+        dataset,
+        traits,
+        std_errors,
+        strains,
+        model,
+        n_perms,
+        seed,
+        Domain::Permutation,
+        early_stop,
+        |scan| {
+            locus_regression(
+                scan.traits,
+                scan.genotypes,
+                scan.dominance,
+                &scan.columns,
+                scan.weights,
+            )
+            .lrs
+        },
+    )
+}
+
+/// Permutation distribution of the maximum interaction LRS, for the
+/// interactive covariates of `model`
+pub fn interaction_permutation(
+    dataset: &Dataset,
+    traits: &[Option<f64>],
+    std_errors: Option<&[Option<f64>]>,
+    strains: &[String],
+    model: &Model,
+    n_perms: usize,
+    seed: u64,
+) -> Result<Vec<f64>, Error> {
+    let interactive = model.interactive_columns()?;
+
+    permutation_maxima(
+        dataset,
+        traits,
+        std_errors,
+        strains,
+        model,
+        n_perms,
+        seed,
+        Domain::InteractionPermutation,
+        None,
+        |scan| {
+            let interactive: Vec<_> =
+                interactive.iter().map(|ix| scan.covariates[*ix]).collect();
+            let additive = locus_regression(
+                scan.traits,
+                scan.genotypes,
+                scan.dominance,
+                &scan.columns,
+                scan.weights,
+            );
+            let lrs_full = regression_interaction(
+                scan.traits,
+                scan.genotypes,
+                scan.dominance,
+                &scan.columns,
+                &interactive,
+                scan.weights,
+            );
+            (lrs_full - additive.lrs).max(0.0)
+        },
+    )
+}
+
+// the permuted data at a single locus of a permutation scan
+struct PermutedLocus<'a> {
+    traits: &'a [f64],
+    genotypes: &'a [f64],
+    dominance: Option<&'a [f64]>,
+    // the control markers of the locus followed by the covariates
+    columns: Vec<&'a [f64]>,
+    covariates: &'a [&'a [f64]],
+    weights: Option<&'a [f64]>,
+}
+
+// the permuted data of a permutation scan, allocated once per worker
+struct PermutationBuffers {
+    order: Vec<usize>,
+    traits: Vec<f64>,
+    weights: Option<Vec<f64>>,
+    covariates: Vec<Vec<f64>>,
+    genotypes: Vec<f64>,
+    dominance: Vec<f64>,
+}
+
+// the sorted maxima over all loci of `statistic`, scanned with the
+// control markers, dominance, weights and covariates of the model
+#[allow(clippy::too_many_arguments)]
+fn permutation_maxima<F>(
+    dataset: &Dataset,
+    traits: &[Option<f64>],
+    std_errors: Option<&[Option<f64>]>,
+    strains: &[String],
+    model: &Model,
+    n_perms: usize,
+    seed: u64,
+    domain: Domain,
+    early_stop: Option<EarlyStop>,
+    statistic: F,
+) -> Result<Vec<f64>, Error>
+where
+    F: Fn(&PermutedLocus) -> f64 + Sync,
+{
+    // the control markers are genotypes, so they stay with the scanned
+    // loci while the traits are permuted
+    let (observed, cofactors) =
+        Observed::with_cofactors(dataset, traits, std_errors, strains, model)?;
+    let strain_ixs = &observed.strain_ixs;
+    let n_strains = strain_ixs.len();
+
+    // the buffers of a worker, reused for each permutation it scans
+    let buffers = || PermutationBuffers {
+        order: vec![0; n_strains],
+        traits: vec![0.0; n_strains],
+        weights: observed.weights.clone(),
+        covariates: observed.covariates.clone(),
+        genotypes: vec![0.0; n_strains],
+        dominance: vec![0.0; n_strains],
+    };
+
+    // the maximum over all loci for the permutation of `stream`
+    let permutation_max = |buffers: &mut PermutationBuffers, stream: usize| {
+        let PermutationBuffers {
+            order,
+            traits: p_traits,
+            weights: p_weights,
+            covariates: p_covariates,
+            genotypes,
+            dominance,
+        } = buffers;
+
+        // trait values are permuted together with their weights and
+        // covariates, so that each value keeps its own weight and the
+        // covariate effects are left intact
+        permuted_mut(order, &mut stream_rng(seed, domain, stream));
+        gather(&observed.traits, order, p_traits);
+        if let (Some(w), Some(p_w)) = (&observed.weights, p_weights.as_mut()) {
+            gather(w, order, p_w);
+        }
+        for (c, p_c) in observed.covariates.iter().zip(p_covariates.iter_mut())
+        {
+            gather(c, order, p_c);
+        }
+        let covariates: Vec<_> = p_covariates.iter().map(|c| &c[..]).collect();
+
+        let mut lrs_max = 0.0;
+
+        for (_, loci) in dataset.genome.chromosomes.iter() {
+            for locus in loci.iter() {
+                locus.genotypes_subindices(strain_ixs, genotypes);
+                if dataset.dominance {
+                    locus.dominance_subindices(strain_ixs, dominance);
+                }
+
+                let mut columns = cofactors.columns(&[&locus.marker]);
+                columns.extend_from_slice(&covariates);
+
+                let lrs = statistic(&PermutedLocus {
+                    traits: p_traits,
+                    genotypes,
+                    dominance: if dataset.dominance {
+                        Some(dominance)
+                    } else {
+                        None
+                    },
+                    columns,
+                    covariates: &covariates,
+                    weights: p_weights.as_ref().map(|w| &w[..]),
+                });
+                lrs_max = lrs.max(lrs_max);
+            }
+        }
+        lrs_max
+    };
+
+    // the maxima of a range of permutations, in stream order
+    let maxima = |streams: Range<usize>| -> Vec<f64> {
+        streams
+            .into_par_iter()
+            .map_init(buffers, permutation_max)
+            .collect()
+    };
+
+    Ok(run_permutations(
+        n_perms,
+        rayon::current_num_threads(),
+        early_stop,
+        maxima,
+    ))
+}
+
+// the number of permutations scanned together as the columns of a matrix
+const PERMUTATION_BATCH: usize = 64;
+
+// Permutation maxima of a scan without weights or covariates, for which
+// the permuted traits can be scanned in batches as matrix products. The
+// result is the same as that of `permutation_maxima` with
+// `locus_regression`, up to rounding.
+fn batched_permutation_maxima(
+    dataset: &Dataset,
+    traits: &[Option<f64>],
+    strains: &[String],
+    model: &Model,
+    n_perms: usize,
+    seed: u64,
+    early_stop: Option<EarlyStop>,
+) -> Result<Vec<f64>, Error> {
+    let (observed, cofactors) =
+        Observed::with_cofactors(dataset, traits, None, strains, model)?;
+    let strain_ixs = &observed.strain_ixs;
+    let n_strains = strain_ixs.len();
+
+    let scan = BatchedScan::new(dataset, &cofactors, strain_ixs);
+
+    // permutations keep the mean, so the traits are centred once, which
+    // keeps the residual sums of squares accurate
+    let mean = observed.traits.iter().sum::<f64>() / n_strains as f64;
+    let centred: Vec<f64> = observed.traits.iter().map(|y| y - mean).collect();
+
+    let batch_maxima = |streams: Range<usize>| {
+        let mut order: Vec<usize> = (0..n_strains).collect();
+        let mut y = Array2::zeros((n_strains, streams.len()));
+        for (col, stream) in streams.enumerate() {
+            permuted_mut(
+                &mut order,
+                &mut stream_rng(seed, Domain::Permutation, stream),
+            );
+            for (row, ix) in order.iter().enumerate() {
+                y[[row, col]] = centred[*ix];
+            }
+        }
+        scan.maxima(&y)
+    };
+
+    let maxima = |streams: Range<usize>| -> Vec<f64> {
+        let batches: Vec<_> = streams
+            .clone()
+            .step_by(PERMUTATION_BATCH)
+            .map(|start| start..streams.end.min(start + PERMUTATION_BATCH))
+            .collect();
+        let maxima: Vec<Vec<f64>> =
+            batches.into_par_iter().map(batch_maxima).collect();
+        maxima.into_iter().flatten().collect()
+    };
+
+    let round_size = PERMUTATION_BATCH * rayon::current_num_threads();
+    Ok(run_permutations(n_perms, round_size, early_stop, maxima))
+}
+
+// The loci of a scan without weights or covariates, prepared for
+// scanning many traits at once. Loci are grouped by the control markers
+// that are fitted with them. Within a group, the null model is an
+// orthonormal basis of the intercept and the control markers, and each
+// locus is an orthonormal basis of its genotype (and dominance) columns
+// once the null model is projected out, so that the reduction in RSS
+// from the null model is the squared norm of the locus basis times the
+// trait.
+struct BatchedScan {
+    groups: Vec<LocusGroup>,
+}
+
+struct LocusGroup {
+    // one row per column of the null model
+    null: Array2<f64>,
+    // `width` rows per locus; all zero for loci whose model cannot be
+    // fitted, which get an LRS of zero as in `locus_regression`
+    loci: Array2<f64>,
+    width: usize,
+}
+
+impl BatchedScan {
+    fn new(
+        dataset: &Dataset,
+        cofactors: &Cofactors,
+        strain_ixs: &[usize],
+    ) -> BatchedScan {
+        let n = strain_ixs.len();
+        let width = if dataset.dominance { 2 } else { 1 };
+
+        let mut grouped: Vec<(Vec<bool>, Vec<&Locus>)> = Vec::new();
+        for locus in dataset.genome.chromosomes.values().flatten() {
+            let included = cofactors.included(&[&locus.marker]);
+            match grouped.iter_mut().find(|(i, _)| *i == included) {
+                Some((_, loci)) => loci.push(locus),
+                None => grouped.push((included, vec![locus])),
+            }
+        }
+
+        let ones = vec![1.0; n];
+        let groups = grouped
+            .into_iter()
+            .map(|(included, loci)| {
+                let mut columns: Vec<&[f64]> = vec![&ones];
+                columns.extend(cofactors.columns_where(&included));
+
+                let mut null = Vec::new();
+                let null_fits = linalg::orthonormalise(&mut null, &columns);
+
+                let mut rows = Array2::zeros((loci.len() * width, n));
+                for (ix, locus) in loci.iter().enumerate() {
+                    let columns = genotype_columns(dataset, locus, strain_ixs);
+                    let columns: Vec<_> =
+                        columns.iter().map(|c| &c[..]).collect();
+
+                    let mut basis = null.clone();
+                    if null_fits && linalg::orthonormalise(&mut basis, &columns)
+                    {
+                        for (k, b) in basis[null.len()..].iter().enumerate() {
+                            rows.row_mut(ix * width + k).assign(b);
+                        }
+                    }
+                }
+
+                LocusGroup {
+                    null: Array2::from_shape_fn((null.len(), n), |(i, j)| {
+                        null[i][j]
+                    }),
+                    loci: rows,
+                    width,
+                }
+            })
+            .collect();
+
+        BatchedScan { groups }
+    }
+
+    // the maximum LRS over all loci of each column of `y`, which holds
+    // centred trait values
+    fn maxima(&self, y: &Array2<f64>) -> Vec<f64> {
+        let n = y.rows() as f64;
+        let total = y.mapv(|v| v * v).sum_axis(Axis(0));
+
+        let mut maxima = vec![0.0; y.cols()];
+        for group in self.groups.iter() {
+            let null = group.null.dot(y);
+            let rss0 = &total - &null.mapv(|v| v * v).sum_axis(Axis(0));
+
+            // within a group the LRS increases with the reduction in RSS,
+            // so only the largest reduction of each column is needed
+            let mut fitted = group.loci.dot(y);
+            fitted.mapv_inplace(|v| v * v);
+            let mut ssr_max = Array1::<f64>::zeros(y.cols());
+            let mut ssr = Array1::<f64>::zeros(y.cols());
+            for rows in fitted.axis_chunks_iter(Axis(0), group.width) {
+                ssr.fill(0.0);
+                for row in rows.genrows() {
+                    ssr += &row;
+                }
+                azip!(mut ssr_max, ssr in { *ssr_max = ssr_max.max(ssr) });
+            }
+
+            for (max, (rss0, ssr)) in
+                maxima.iter_mut().zip(rss0.iter().zip(ssr_max.iter()))
+            {
+                let lrs = n * (rss0 / (rss0 - ssr)).ln();
+                // NaN is never greater
+                if lrs > *max {
+                    *max = lrs;
+                }
+            }
+        }
+
+        maxima
+    }
+}
+
+// runs the permutations of `maxima`, which scans a range of streams and
+// returns their maxima in stream order, and sorts the maxima
+fn run_permutations<F>(
+    n_perms: usize,
+    round_size: usize,
+    early_stop: Option<EarlyStop>,
+    maxima: F,
+) -> Vec<f64>
+where
+    F: Fn(Range<usize>) -> Vec<f64>,
+{
+    let mut lrs_vec = match early_stop {
+        None => maxima(0..n_perms),
+        Some(stop) => {
+            // permutations are run a round at a time, and cut at the one
+            // that reaches `top_n` maxima above the threshold, so that the
+            // result does not depend on the number of threads
+            let mut lrs_vec = Vec::with_capacity(n_perms);
+            let mut exceeding = 0;
+            'rounds: while lrs_vec.len() < n_perms {
+                let start = lrs_vec.len();
+                let end = n_perms.min(start + round_size);
+                for lrs in maxima(start..end) {
+                    lrs_vec.push(lrs);
+                    if lrs > stop.lrs {
+                        exceeding += 1;
+                        if exceeding >= stop.top_n {
+                            break 'rounds;
+                        }
+                    }
+                }
+            }
+            lrs_vec
+        }
+    };
+
+    lrs_vec.sort_by(|x, y| x.partial_cmp(y).unwrap());
+    lrs_vec
+}
+
+/// Pairwise LRS of a two-dimensional scan, with rows and columns in the
+/// order of `markers`. The matrices are symmetric, and zero on the
+/// diagonal.
+#[derive(Debug, Serialize)]
+pub struct ScanTwo {
+    pub markers: Vec<Marker>,
+    /// Both loci and their interaction, against the model without them
+    pub lrs_full: Vec<Vec<f64>>,
+    /// Both loci without their interaction
+    pub lrs_additive: Vec<Vec<f64>>,
+    /// The interaction terms alone, `lrs_full - lrs_additive`
+    pub lrs_interaction: Vec<Vec<f64>>,
+}
+
+/// Permutation distributions of the genome-wide maximum full and
+/// interaction LRS of a two-dimensional scan, sorted, and the thresholds
+/// they give
+#[derive(Debug, Serialize)]
+pub struct ScanTwoPermutations {
+    pub full: Vec<f64>,
+    pub interaction: Vec<f64>,
+    pub full_thresholds: Thresholds,
+    pub interaction_thresholds: Thresholds,
+}
+
+/// Fits every pair of loci in the genome, with the control markers and
+/// additive covariates of `model`; interactive covariates are not used.
+pub fn scantwo(
+    dataset: &Dataset,
+    traits: &[Option<f64>],
+    std_errors: Option<&[Option<f64>]>,
+    strains: &[String],
+    model: &Model,
+) -> Result<ScanTwo, Error> {
+    let (observed, cofactors) =
+        Observed::with_cofactors(dataset, traits, std_errors, strains, model)?;
+    let loci = locus_columns(dataset, &observed.strain_ixs);
+
+    let covariates: Vec<_> =
+        observed.covariates.iter().map(|c| &c[..]).collect();
+    let pairs = scantwo_pairs(
+        &loci,
+        &cofactors,
+        &observed.traits,
+        &covariates,
+        observed.weights(),
+    );
+
+    let n_loci = loci.len();
+    let mut lrs_full = vec![vec![0.0; n_loci]; n_loci];
+    let mut lrs_additive = vec![vec![0.0; n_loci]; n_loci];
+    let mut lrs_interaction = vec![vec![0.0; n_loci]; n_loci];
+
+    for (i, row) in pairs.iter().enumerate() {
+        for (k, &(full, additive)) in row.iter().enumerate() {
+            let j = i + k + 1;
+            let interaction = (full - additive).max(0.0);
+            lrs_full[i][j] = full;
+            lrs_full[j][i] = full;
+            lrs_additive[i][j] = additive;
+            lrs_additive[j][i] = additive;
+            lrs_interaction[i][j] = interaction;
+            lrs_interaction[j][i] = interaction;
+        }
+    }
+
+    Ok(ScanTwo {
+        markers: loci.into_iter().map(|(m, _)| m.clone()).collect(),
+        lrs_full,
+        lrs_additive,
+        lrs_interaction,
+    })
+}
+
+/// Permutation distributions of the maximum full and interaction LRS of
+/// `scantwo`, with thresholds at the significance levels `alphas`
+#[allow(clippy::too_many_arguments)]
+pub fn scantwo_permutation(
+    dataset: &Dataset,
+    traits: &[Option<f64>],
+    std_errors: Option<&[Option<f64>]>,
+    strains: &[String],
+    model: &Model,
+    n_perms: usize,
+    seed: u64,
+    alphas: &[f64],
+) -> Result<ScanTwoPermutations, Error> {
+    let (observed, cofactors) =
+        Observed::with_cofactors(dataset, traits, std_errors, strains, model)?;
+    let loci = locus_columns(dataset, &observed.strain_ixs);
+    let n_strains = observed.strain_ixs.len();
+
+    // the buffers of a worker, reused for each permutation it scans
+    let buffers = || PermutationBuffers {
+        order: vec![0; n_strains],
+        traits: vec![0.0; n_strains],
+        weights: observed.weights.clone(),
+        covariates: observed.covariates.clone(),
+        genotypes: Vec::new(),
+        dominance: Vec::new(),
+    };
+
+    // as in `permutation`, weights and covariates follow their trait
+    // values, and each permutation only depends on its stream
+    let permutation_max = |buffers: &mut PermutationBuffers, stream: usize| {
+        let PermutationBuffers {
+            order,
+            traits: p_traits,
+            weights: p_weights,
+            covariates: p_covariates,
+            ..
+        } = buffers;
+
+        permuted_mut(
+            order,
+            &mut stream_rng(seed, Domain::ScantwoPermutation, stream),
+        );
+        gather(&observed.traits, order, p_traits);
+        if let (Some(w), Some(p_w)) = (&observed.weights, p_weights.as_mut()) {
+            gather(w, order, p_w);
+        }
+        for (c, p_c) in observed.covariates.iter().zip(p_covariates.iter_mut())
+        {
+            gather(c, order, p_c);
+        }
+        let columns: Vec<_> = p_covariates.iter().map(|c| &c[..]).collect();
+
+        let pairs = scantwo_pairs(
+            &loci,
+            &cofactors,
+            p_traits,
+            &columns,
+            p_weights.as_ref().map(|w| &w[..]),
+        );
+
+        let (mut full_max, mut interaction_max) = (0.0, 0.0);
+        for &(f, a) in pairs.iter().flatten() {
+            full_max = f.max(full_max);
+            interaction_max = (f - a).max(interaction_max);
+        }
+        (full_max, interaction_max)
+    };
+
+    let (mut full, mut interaction): (Vec<f64>, Vec<f64>) = (0..n_perms)
+        .into_par_iter()
+        .map_init(buffers, permutation_max)
+        .unzip();
+
+    full.sort_by(|x, y| x.partial_cmp(y).unwrap());
+    interaction.sort_by(|x, y| x.partial_cmp(y).unwrap());
+
+    Ok(ScanTwoPermutations {
+        full_thresholds: Thresholds::new(&full, alphas),
+        interaction_thresholds: Thresholds::new(&interaction, alphas),
+        full,
+        interaction,
+    })
+}
+
+// the marker and the genotype (and, for intercrosses, dominance) columns
+// of every locus, in genome order
+fn locus_columns<'a>(
+    dataset: &'a Dataset,
+    strain_ixs: &[usize],
+) -> Vec<(&'a Marker, Vec<Vec<f64>>)> {
+    dataset
+        .genome
+        .chromosomes
+        .values()
+        .flatten()
+        .map(|locus| {
+            (&locus.marker, genotype_columns(dataset, locus, strain_ixs))
+        })
+        .collect()
+}
+
+// the full and additive LRS of every pair of loci; row `i` holds the
+// pairs of locus `i` with each of the loci after it
+fn scantwo_pairs(
+    loci: &[(&Marker, Vec<Vec<f64>>)],
+    cofactors: &Cofactors,
     traits: &[f64],
+    covariates: &[&[f64]],
+    weights: Option<&[f64]>,
+) -> Vec<Vec<(f64, f64)>> {
+    (0..loci.len())
+        .into_par_iter()
+        .map(|i| {
+            let (first, first_columns) = &loci[i];
+            let first_columns: Vec<_> =
+                first_columns.iter().map(|c| &c[..]).collect();
+
+            loci[i + 1..]
+                .iter()
+                .map(|(second, second_columns)| {
+                    let second_columns: Vec<_> =
+                        second_columns.iter().map(|c| &c[..]).collect();
+                    let mut columns = cofactors.columns(&[first, second]);
+                    columns.extend_from_slice(covariates);
+
+                    regression_pair(
+                        traits,
+                        &first_columns,
+                        &second_columns,
+                        &columns,
+                        weights,
+                    )
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// The interaction of a scanned locus with a fixed marker
+#[derive(Debug, Serialize)]
+pub struct Epistasis {
+    pub marker: Marker,
+    /// LRS of the interaction terms, against the model with both loci
+    pub lrs: f64,
+    /// Effects of the scanned locus in the model with interaction
+    pub additive: f64,
+    pub dominance: Option<f64>,
+    /// Coefficient of the product of the additive genotypes of the two
+    /// loci
+    pub interaction: f64,
+}
+
+// formatter for Epistasis, tab-delimited like QTL
+impl fmt::Display for Epistasis {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}\t{:.*}\t{:.*}",
+            self.marker, 3, self.lrs, 3, self.additive
+        )?;
+
+        if let Some(d) = self.dominance {
+            write!(f, "\t{:.*}", 3, d)?;
+        }
+
+        write!(f, "\t{:.*}", 3, self.interaction)
+    }
+}
+
+struct EpistasisResult {
+    lrs: f64,
+    additive: f64,
+    dominance: Option<f64>,
+    interaction: f64,
+}
+
+/// Scans every locus other than the `fixed` marker for interaction with
+/// it, with the control markers and additive covariates of `model`
+pub fn epistasis(
+    dataset: &Dataset,
+    traits: &[Option<f64>],
+    std_errors: Option<&[Option<f64>]>,
     strains: &[String],
-    control: Option<&str>,
-) -> Vec<QTL> {
-    //
-    let mut result = Vec::with_capacity(dataset.n_loci());
-
-    let strain_ixs = dataset.strain_indices(strains);
-
-    let control_geno: Option<Vec<_>> = control.map(|c| {
-        dataset
-            .genome
-            .find_locus(c)
-            .expect("Could not find control genotype")
-            .genotypes_subset(&strain_ixs)
-    });
+    model: &Model,
+    fixed: &str,
+) -> Result<Vec<Epistasis>, Error> {
+    let (observed, cofactors) =
+        Observed::with_cofactors(dataset, traits, std_errors, strains, model)?;
+    let strain_ixs = &observed.strain_ixs;
+
+    let fixed = find_locus(dataset, fixed)?;
+    let fixed = (&fixed.marker, genotype_columns(dataset, fixed, strain_ixs));
+
+    let loci: Vec<_> = locus_columns(dataset, strain_ixs)
+        .into_iter()
+        .filter(|(marker, _)| marker.name != fixed.0.name)
+        .collect();
+
+    let covariates: Vec<_> =
+        observed.covariates.iter().map(|c| &c[..]).collect();
+
+    let results = epistasis_scan(
+        &loci,
+        &fixed,
+        &cofactors,
+        &observed.traits,
+        &covariates,
+        observed.weights(),
+    );
+
+    Ok(loci
+        .iter()
+        .zip(results)
+        .map(|((marker, _), result)| Epistasis {
+            marker: (*marker).clone(),
+            lrs: result.lrs,
+            additive: result.additive,
+            dominance: result.dominance,
+            interaction: result.interaction,
+        })
+        .collect())
+}
+
+/// Permutation distribution of the maximum interaction LRS with the
+/// `fixed` marker. The residuals of the model with the fixed marker are
+/// permuted and added back to its fitted values, so that the main effect
+/// of the fixed marker is kept in every permutation.
+#[allow(clippy::too_many_arguments)]
+pub fn epistasis_permutation(
+    dataset: &Dataset,
+    traits: &[Option<f64>],
+    std_errors: Option<&[Option<f64>]>,
+    strains: &[String],
+    model: &Model,
+    fixed: &str,
+    n_perms: usize,
+    seed: u64,
+) -> Result<Vec<f64>, Error> {
+    let (observed, cofactors) =
+        Observed::with_cofactors(dataset, traits, std_errors, strains, model)?;
+    let strain_ixs = &observed.strain_ixs;
+    let n_strains = strain_ixs.len();
+
+    let fixed = find_locus(dataset, fixed)?;
+    let fixed = (&fixed.marker, genotype_columns(dataset, fixed, strain_ixs));
+
+    let loci: Vec<_> = locus_columns(dataset, strain_ixs)
+        .into_iter()
+        .filter(|(marker, _)| marker.name != fixed.0.name)
+        .collect();
+
+    let weights = observed.weights();
+    let covariates: Vec<_> =
+        observed.covariates.iter().map(|c| &c[..]).collect();
+
+    // the residuals are scaled by the standard errors before they are
+    // permuted, and each strain scales them back by its own
+    let traits = &observed.traits;
+    let scale: Vec<f64> = match weights {
+        None => vec![1.0; n_strains],
+        Some(w) => w.iter().map(|w| 1.0 / w.sqrt()).collect(),
+    };
 
-    if control != None && control_geno == None {
-        panic!("Control could not be found in loci list");
+    let mut columns: Vec<_> = fixed.1.iter().map(|c| &c[..]).collect();
+    columns.extend(cofactors.columns(&[fixed.0]));
+    columns.extend_from_slice(&covariates);
+    let x = linalg::design_matrix(n_strains, &columns);
+    let fitted: Vec<f64> = match linalg::least_squares(traits, &x, weights) {
+        Some(fit) => x.dot(&fit.coefficients).to_vec(),
+        None => vec![0.0; n_strains],
+    };
+    let residuals: Vec<f64> = (0..n_strains)
+        .map(|ix| (traits[ix] - fitted[ix]) / scale[ix])
+        .collect();
+
+    let mut lrs_vec: Vec<_> = (0..n_perms)
+        .into_par_iter()
+        .map(|stream| {
+            let mut order: Vec<usize> = (0..n_strains).collect();
+            permuted_mut(
+                &mut order,
+                &mut stream_rng(seed, Domain::EpistasisPermutation, stream),
+            );
+            let p_traits: Vec<_> = (0..n_strains)
+                .map(|ix| fitted[ix] + scale[ix] * residuals[order[ix]])
+                .collect();
+
+            epistasis_scan(
+                &loci,
+                &fixed,
+                &cofactors,
+                &p_traits,
+                &covariates,
+                weights,
+            )
+            .iter()
+            .fold(0.0, |max, result| result.lrs.max(max))
+        })
+        .collect();
+
+    lrs_vec.sort_by(|x, y| x.partial_cmp(y).unwrap());
+    Ok(lrs_vec)
+}
+
+fn epistasis_scan(
+    loci: &[(&Marker, Vec<Vec<f64>>)],
+    fixed: &(&Marker, Vec<Vec<f64>>),
+    cofactors: &Cofactors,
+    traits: &[f64],
+    covariates: &[&[f64]],
+    weights: Option<&[f64]>,
+) -> Vec<EpistasisResult> {
+    let fixed_columns: Vec<_> = fixed.1.iter().map(|c| &c[..]).collect();
+
+    loci.iter()
+        .map(|(marker, scanned)| {
+            let scanned: Vec<_> = scanned.iter().map(|c| &c[..]).collect();
+            let mut columns = cofactors.columns(&[marker, fixed.0]);
+            columns.extend_from_slice(covariates);
+
+            regression_epistasis(
+                traits,
+                &scanned,
+                &fixed_columns,
+                &columns,
+                weights,
+            )
+        })
+        .collect()
+}
+
+fn find_locus<'a>(
+    dataset: &'a Dataset,
+    name: &str,
+) -> Result<&'a Locus, Error> {
+    dataset
+        .genome
+        .find_locus(name)
+        .ok_or_else(|| Error::UnknownMarker {
+            name: name.to_string(),
+        })
+}
+
+// the genotype (and, for intercrosses, dominance) columns of a locus
+fn genotype_columns(
+    dataset: &Dataset,
+    locus: &Locus,
+    strain_ixs: &[usize],
+) -> Vec<Vec<f64>> {
+    let mut columns = vec![locus.genotypes_subset(strain_ixs)];
+    if dataset.dominance {
+        columns.push(locus.dominance_subset(strain_ixs));
+    }
+    columns
+}
+
+/// The LRS that a genome-wide maximum from `permutations` exceeds with
+/// probability `alpha`. `permutations` must be sorted.
+pub fn threshold(permutations: &[f64], alpha: f64) -> f64 {
+    let n = permutations.len();
+    if n == 0 {
+        return 0.0;
+    }
+    let ix = ((1.0 - alpha) * n as f64).ceil() as usize;
+    permutations[ix.clamp(1, n) - 1]
+}
+
+/// A locus in a multiple-QTL model
+#[derive(Debug, Serialize)]
+pub struct ModelQtl {
+    pub marker: Marker,
+    /// LRS of the model against the model without this locus
+    pub lrs: f64,
+    pub additive: f64,
+    pub dominance: Option<f64>,
+    /// Percentage of the trait variance explained by this locus, given
+    /// the other loci in the model
+    pub variance_explained: f64,
+}
+
+// formatter for ModelQtl, tab-delimited like QTL
+impl fmt::Display for ModelQtl {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}\t{:.*}\t{:.*}",
+            self.marker, 3, self.lrs, 3, self.additive
+        )?;
+
+        if let Some(d) = self.dominance {
+            write!(f, "\t{:.*}", 3, d)?;
+        }
+
+        write!(f, "\t{:.*}", 3, self.variance_explained)
+    }
+}
+
+/// A multiple-QTL model found by stepwise selection
+#[derive(Debug, Serialize)]
+pub struct MultipleQtl {
+    pub qtls: Vec<ModelQtl>,
+    /// LRS of the model against the model without any of its loci
+    pub lrs: f64,
+    /// Percentage of the trait variance explained by the loci together
+    pub variance_explained: f64,
+}
+
+/// Selects a multiple-QTL model by forward selection followed by backward
+/// elimination, on top of the control markers and covariates of `model`.
+///
+/// Loci are added while the best locus of a scan with the loci selected
+/// so far as control markers has an LRS above `threshold`, up to
+/// `max_qtl` loci. Loci whose LRS given the others is below `threshold`
+/// are then dropped, weakest first. This maximises the LRS of the model
+/// penalised by `threshold` for every locus.
+pub fn stepwise(
+    dataset: &Dataset,
+    traits: &[Option<f64>],
+    std_errors: Option<&[Option<f64>]>,
+    strains: &[String],
+    model: &Model,
+    threshold: f64,
+    max_qtl: usize,
+) -> Result<MultipleQtl, Error> {
+    // the control markers and covariates are in every model
+    let (observed, cofactors) =
+        Observed::with_cofactors(dataset, traits, std_errors, strains, model)?;
+    let strain_ixs = &observed.strain_ixs;
+    let traits = &observed.traits;
+    let weights = observed.weights();
+    let n = traits.len();
+
+    // loci are selected by their position in the genome, as the
+    // pseudo-markers of an interval mapped genome all have the same name
+    let genome: Vec<&Locus> =
+        dataset.genome.chromosomes.values().flatten().collect();
+    let mut selected: Vec<usize> = Vec::new();
+
+    while selected.len() < max_qtl {
+        // a scan with the loci selected so far as control markers
+        let loci: Vec<_> = selected.iter().map(|ix| genome[*ix]).collect();
+        let scan_cofactors = cofactors.with_loci(dataset, &loci, strain_ixs);
+
+        let best = (0..genome.len())
+            .into_par_iter()
+            .filter(|ix| !selected.contains(ix))
+            .map(|ix| {
+                let locus = genome[ix];
+                let columns = genotype_columns(dataset, locus, strain_ixs);
+                let mut cofactors = scan_cofactors.columns(&[&locus.marker]);
+                cofactors.extend(observed.covariates.iter().map(|c| &c[..]));
+                let lrs = locus_regression(
+                    traits,
+                    &columns[0],
+                    columns.get(1).map(|d| &d[..]),
+                    &cofactors,
+                    weights,
+                )
+                .lrs;
+                (ix, lrs)
+            })
+            .collect::<Vec<_>>()
+            .into_iter()
+            .max_by(|x, y| x.1.partial_cmp(&y.1).unwrap());
+        match best {
+            Some((ix, lrs)) if lrs > threshold => selected.push(ix),
+            _ => break,
+        }
+    }
+
+    let mut background = cofactors.columns(&[]);
+    background.extend(observed.covariates.iter().map(|c| &c[..]));
+
+    let mut loci: Vec<_> = selected
+        .iter()
+        .map(|ix| {
+            let locus = genome[*ix];
+            (locus, genotype_columns(dataset, locus, strain_ixs))
+        })
+        .collect();
+
+    let fit = |loci: &[(&Locus, Vec<Vec<f64>>)], skip: Option<usize>| {
+        let mut columns: Vec<&[f64]> = loci
+            .iter()
+            .enumerate()
+            .filter(|(ix, _)| Some(*ix) != skip)
+            .flat_map(|(_, (_, c))| c.iter().map(|c| &c[..]))
+            .collect();
+        columns.extend_from_slice(&background);
+        linalg::least_squares(
+            traits,
+            &linalg::design_matrix(n, &columns),
+            weights,
+        )
+    };
+    let lrs = |reduced: &Option<linalg::Fit>, full: &Option<linalg::Fit>| match (
+        reduced, full,
+    ) {
+        (Some(reduced), Some(full)) => {
+            let lrs = (n as f64) * (reduced.rss / full.rss).ln();
+            if lrs.is_nan() || lrs < 0.0 {
+                0.0
+            } else {
+                lrs
+            }
+        }
+        _ => 0.0,
+    };
+
+    loop {
+        let full = fit(&loci, None);
+        let weakest = (0..loci.len())
+            .map(|ix| (ix, lrs(&fit(&loci, Some(ix)), &full)))
+            .min_by(|x, y| x.1.partial_cmp(&y.1).unwrap());
+        match weakest {
+            Some((ix, lrs)) if lrs <= threshold => {
+                loci.remove(ix);
+            }
+            _ => break,
+        }
+    }
+
+    let full = fit(&loci, None);
+    let null = fit(&[], None);
+    let tss =
+        linalg::least_squares(traits, &linalg::design_matrix(n, &[]), weights)
+            .map_or(0.0, |fit| fit.rss);
+    let explained = |reduced: &Option<linalg::Fit>| match (reduced, &full) {
+        (Some(reduced), Some(full)) if tss > 0.0 => {
+            100.0 * (reduced.rss - full.rss) / tss
+        }
+        _ => 0.0,
+    };
+
+    let mut qtls = Vec::with_capacity(loci.len());
+    // the coefficients of the loci follow the intercept, in order
+    let mut coefficient = 1;
+    for (ix, (locus, columns)) in loci.iter().enumerate() {
+        let reduced = fit(&loci, Some(ix));
+        let effect = |offset: usize| {
+            full.as_ref()
+                .map_or(0.0, |full| full.coefficients[coefficient + offset])
+        };
+
+        qtls.push(ModelQtl {
+            marker: locus.marker.clone(),
+            lrs: lrs(&reduced, &full),
+            additive: effect(0),
+            dominance: if columns.len() > 1 {
+                Some(effect(1))
+            } else {
+                None
+            },
+            variance_explained: explained(&reduced),
+        });
+        coefficient += columns.len();
+    }
+
+    Ok(MultipleQtl {
+        qtls,
+        lrs: lrs(&null, &full),
+        variance_explained: explained(&null),
+    })
+}
+
+/// An interval of a chromosome between two markers, which are included
+#[derive(Clone, Debug, Serialize)]
+pub struct Interval {
+    pub chromosome: String,
+    pub start: Marker,
+    pub end: Marker,
+}
+
+/// The loci at which bootstrap samples of a trait have their highest LRS
+#[derive(Debug, Serialize)]
+pub struct Bootstrap {
+    pub n_samples: usize,
+    /// Every locus, with the number of samples that peak at it
+    pub counts: Vec<(Marker, usize)>,
+    /// The 95% bootstrap confidence interval of the top peak: the middle
+    /// 95% of the positions of the highest LRS of each sample on the
+    /// chromosome of the highest LRS of the trait. `None` without samples
+    /// or loci.
+    pub interval: Option<Interval>,
+}
+
+pub fn bootstrap(
+    dataset: &Dataset,
+    traits: &[Option<f64>],
+    std_errors: Option<&[Option<f64>]>,
+    strains: &[String],
+    model: &Model,
+    n_boot: usize,
+    seed: u64,
+) -> Result<Bootstrap, Error> {
+    let (observed, cofactors) =
+        Observed::with_cofactors(dataset, traits, std_errors, strains, model)?;
+    let traits = &observed.traits;
+    let strain_ixs = &observed.strain_ixs;
+
+    let loci: Vec<_> = dataset.genome.chromosomes.values().flatten().collect();
+
+    // the control markers fitted at each locus
+    let included: Vec<Vec<bool>> = loci
+        .iter()
+        .map(|locus| cofactors.included(&[&locus.marker]))
+        .collect();
+
+    // the LRS of every locus, for traits resampled by `indices`, with the
+    // same model as `regression`
+    let scan = |indices: &[usize]| -> Vec<f64> {
+        let resample = |values: &[f64]| -> Vec<f64> {
+            indices.iter().cloned().map(|ix| values[ix]).collect()
+        };
+
+        let b_traits = resample(traits);
+        let b_weights = observed.weights.as_ref().map(|w| resample(w));
+        let b_cofactors = cofactors.resampled(indices);
+        let b_covariates: Vec<_> =
+            observed.covariates.iter().map(|c| resample(c)).collect();
+
+        // the genotype file columns of the resampled strains, and buffers
+        // that are reused for every locus
+        let b_strain_ixs: Vec<usize> =
+            indices.iter().map(|ix| strain_ixs[*ix]).collect();
+        let mut genotypes = vec![0.0; indices.len()];
+        let mut dominance = vec![0.0; indices.len()];
+        let mut columns: Vec<&[f64]> = Vec::new();
+        let mut designs = Designs::default();
+
+        loci.iter()
+            .zip(included.iter())
+            .map(|(locus, included)| {
+                locus.genotypes_subindices(&b_strain_ixs, &mut genotypes);
+                if dataset.dominance {
+                    locus.dominance_subindices(&b_strain_ixs, &mut dominance);
+                }
+
+                columns.clear();
+                columns.extend(b_cofactors.columns_where(included));
+                columns.extend(b_covariates.iter().map(|c| &c[..]));
+
+                locus_regression_with(
+                    &b_traits,
+                    &genotypes,
+                    if dataset.dominance {
+                        Some(&dominance)
+                    } else {
+                        None
+                    },
+                    &columns,
+                    b_weights.as_ref().map(|w| &w[..]),
+                    &mut designs,
+                )
+                .lrs
+            })
+            .collect()
+    };
+
+    // the first of the highest LRS among the loci in `range`
+    let peak = |lrs: &[f64], range: Range<usize>| {
+        let mut lrs_max = 0.0;
+        let mut lrs_max_pos = range.start;
+        for ix in range {
+            if lrs_max < lrs[ix] {
+                lrs_max_pos = ix;
+                lrs_max = lrs[ix];
+            }
+        }
+        lrs_max_pos
+    };
+
+    // a genome without loci has no peak to resample
+    if loci.is_empty() {
+        return Ok(Bootstrap {
+            n_samples: n_boot,
+            counts: Vec::new(),
+            interval: None,
+        });
+    }
+
+    let identity: Vec<usize> = (0..traits.len()).collect();
+    let top = peak(&scan(&identity), 0..loci.len());
+    let chromosome = &loci[top].marker.chromosome;
+    let first = loci
+        .iter()
+        .position(|l| &l.marker.chromosome == chromosome)
+        .unwrap_or(0);
+    let last = loci
+        .iter()
+        .rposition(|l| &l.marker.chromosome == chromosome)
+        .unwrap_or(0);
+
+    // the positions of the highest LRS of each sample, in the genome and
+    // on the chromosome of the top peak
+    let positions: Vec<(usize, usize)> = (0..n_boot)
+        .into_par_iter()
+        .map(|ix| {
+            let indices = bootstrap_indices(
+                traits,
+                &mut stream_rng(seed, Domain::Bootstrap, ix),
+            );
+            let lrs = scan(&indices);
+            (peak(&lrs, 0..loci.len()), peak(&lrs, first..last + 1))
+        })
+        .collect();
+
+    let mut locus_count = vec![0; loci.len()];
+    for (pos, _) in positions.iter() {
+        locus_count[*pos] += 1;
+    }
+
+    let mut peaks: Vec<_> = positions.iter().map(|(_, pos)| *pos).collect();
+    peaks.sort_by(|x, y| loci[*x].cm().partial_cmp(&loci[*y].cm()).unwrap());
+    let interval = if peaks.is_empty() {
+        None
+    } else {
+        let n = peaks.len() as f64;
+        let lower = (0.025 * n).floor() as usize;
+        let upper = ((0.975 * n).ceil() as usize).clamp(1, peaks.len()) - 1;
+        Some(Interval {
+            chromosome: chromosome.clone(),
+            start: loci[peaks[lower]].marker.clone(),
+            end: loci[peaks[upper]].marker.clone(),
+        })
+    };
+
+    Ok(Bootstrap {
+        n_samples: n_boot,
+        counts: loci
+            .iter()
+            .map(|locus| locus.marker.clone())
+            .zip(locus_count)
+            .collect(),
+        interval,
+    })
+}
+
+/// The highest LOD on a chromosome and the intervals that support it
+#[derive(Debug, Serialize)]
+pub struct PeakIntervals {
+    pub peak: Marker,
+    pub lod: f64,
+    /// The loci within the LOD drop of the peak around it
+    pub lod_drop: Interval,
+    /// The loci with the highest posterior density that together hold
+    /// the credible probability, with a posterior proportional to 10^LOD
+    pub bayes: Interval,
+}
+
+/// LOD drop and Bayes credible intervals of the peak on each chromosome of
+/// a scan from `regression`, which has the loci of each chromosome
+/// together and in order. Both intervals are widened to the nearest
+/// markers from the genotype file, so that on an interval mapped scan
+/// they are flanked by markers.
+pub fn support_intervals(
+    qtls: &[QTL],
+    lod_drop: f64,
+    probability: f64,
+) -> Vec<PeakIntervals> {
+    let mut result = Vec::new();
+
+    for range in chromosome_ranges(qtls) {
+        let loci = &qtls[range];
+        let chromosome = &loci[0].marker.chromosome;
+
+        let lod: Vec<f64> = loci.iter().map(|qtl| qtl.lod).collect();
+        let peak =
+            (0..lod.len())
+                .fold(0, |p, ix| if lod[ix] > lod[p] { ix } else { p });
+
+        // the loci around the peak that are within the drop
+        let within = |ix: &usize| lod[*ix] >= lod[peak] - lod_drop;
+        let drop_start =
+            (0..peak).rev().take_while(within).last().unwrap_or(peak);
+        let drop_end = (peak + 1..lod.len())
+            .take_while(within)
+            .last()
+            .unwrap_or(peak);
+
+        // 10^LOD relative to the peak. A perfect fit has an infinite LOD,
+        // so the loci that fit perfectly hold all of the posterior.
+        let density = |ix: usize| {
+            if lod[ix] == lod[peak] {
+                1.0
+            } else {
+                10f64.powf(lod[ix] - lod[peak])
+            }
+        };
+
+        // the area under 10^LOD that each locus stands for, halfway to
+        // its neighbours
+        let cm = |ix: usize| loci[ix].marker.centi_morgan;
+        let n = loci.len();
+        let mut area: Vec<f64> = (0..n)
+            .map(|ix| {
+                let width =
+                    (cm((ix + 1).min(n - 1)) - cm(ix.saturating_sub(1))) / 2.0;
+                density(ix) * width
+            })
+            .collect();
+        let total: f64 = area.iter().sum();
+        if total <= 0.0 {
+            // a chromosome of a single position, or of positions that
+            // are all at the same cM
+            area = (0..n).map(density).collect();
+        }
+        let total: f64 = area.iter().sum();
+
+        // the loci in order of posterior density, until they hold the
+        // probability
+        let mut order: Vec<usize> = (0..n).collect();
+        order.sort_by(|x, y| lod[*y].total_cmp(&lod[*x]));
+        let mut cumulative = 0.0;
+        let mut bayes_start = peak;
+        let mut bayes_end = peak;
+        for ix in order {
+            bayes_start = bayes_start.min(ix);
+            bayes_end = bayes_end.max(ix);
+            cumulative += area[ix] / total;
+            if cumulative >= probability {
+                break;
+            }
+        }
+
+        let interval = |start: usize, end: usize| {
+            let start = (0..=start)
+                .rev()
+                .find(|ix| !loci[*ix].marker.is_pseudo())
+                .unwrap_or(0);
+            let end = (end..n)
+                .find(|ix| !loci[*ix].marker.is_pseudo())
+                .unwrap_or(n - 1);
+            Interval {
+                chromosome: chromosome.clone(),
+                start: loci[start].marker.clone(),
+                end: loci[end].marker.clone(),
+            }
+        };
+
+        result.push(PeakIntervals {
+            peak: loci[peak].marker.clone(),
+            lod: lod[peak],
+            lod_drop: interval(drop_start, drop_end),
+            bayes: interval(bayes_start, bayes_end),
+        });
+    }
+
+    result
+}
+
+// the ranges of `qtls` that hold the loci of each chromosome
+fn chromosome_ranges(qtls: &[QTL]) -> Vec<Range<usize>> {
+    let mut ranges = Vec::new();
+    let mut start = 0;
+    while start < qtls.len() {
+        let chromosome = &qtls[start].marker.chromosome;
+        let end = qtls[start..]
+            .iter()
+            .position(|qtl| &qtl.marker.chromosome != chromosome)
+            .map_or(qtls.len(), |len| start + len);
+        ranges.push(start..end);
+        start = end;
+    }
+    ranges
+}
+
+/// The loci with the highest LRS on each chromosome of a scan from
+/// `regression`. With `min_drop`, a chromosome can have several peaks: a
+/// locus is another peak if, between it and each higher peak, the LOD
+/// drops by at least `min_drop` below its own. Peaks are in genome order.
+pub fn peaks(qtls: &[QTL], min_drop: Option<f64>) -> Vec<&QTL> {
+    let mut result = Vec::new();
+
+    for range in chromosome_ranges(qtls) {
+        let loci = &qtls[range];
+
+        let mut order: Vec<usize> = (0..loci.len()).collect();
+        order.sort_by(|x, y| loci[*y].lrs.total_cmp(&loci[*x].lrs));
+
+        let mut found: Vec<usize> = vec![order[0]];
+        if let Some(drop) = min_drop {
+            for &ix in order[1..].iter() {
+                let lod = loci[ix].lod;
+                let separated = found.iter().all(|&peak| {
+                    let between = ix.min(peak)..=ix.max(peak);
+                    loci[between].iter().any(|qtl| qtl.lod <= lod - drop)
+                });
+                if separated {
+                    found.push(ix);
+                }
+            }
+        }
+
+        found.sort();
+        result.extend(found.into_iter().map(|ix| &loci[ix]));
+    }
+
+    result
+}
+
+/// A peak of a scan with its genome-wide p-values, for the summary of a
+/// trait
+#[derive(Debug, Serialize)]
+pub struct Peak {
+    pub marker: Marker,
+    pub lrs: f64,
+    pub additive: f64,
+    /// The empirical genome-wide p-value from the permutations
+    pub pvalue: f64,
+    /// The genome-wide p-value from the extreme value distribution fitted
+    /// to the permutations, `None` if it could not be fitted
+    pub pvalue_gev: Option<f64>,
+}
+
+impl Peak {
+    pub fn new(qtl: &QTL, permutations: &Permutations) -> Peak {
+        Peak {
+            marker: qtl.marker.clone(),
+            lrs: qtl.lrs,
+            additive: qtl.additive,
+            pvalue: pvalue(qtl.lrs, &permutations.lrs),
+            pvalue_gev: permutations.pvalue_gev(qtl.lrs),
+        }
+    }
+}
+
+// formatter for Peak, tab-delimited like QTL
+impl fmt::Display for Peak {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}\t{:.*}\t{:.*}\t{:.*}",
+            self.marker, 3, self.lrs, 3, self.additive, 3, self.pvalue
+        )?;
+
+        match self.pvalue_gev {
+            Some(p) => write!(f, "\t{:.*e}", 3, p),
+            None => write!(f, "\tNA"),
+        }
     }
+}
 
-    for (_, loci) in dataset.genome.chromosomes.iter() {
-        for locus in loci.iter() {
-            let genotypes = locus.genotypes_subset(&strain_ixs);
+/// The strains of a trait that take part in a scan: those that have a
+/// value, all covariates and, if standard errors are given, a positive
+/// standard error
+struct Observed {
+    traits: Vec<f64>,
+    // the inverse squared standard errors, used as weights
+    weights: Option<Vec<f64>>,
+    // one column per covariate
+    covariates: Vec<Vec<f64>>,
+    strain_ixs: Vec<usize>,
+}
 
-            let reg_result = match &control_geno {
-                None => {
-                    if dataset.dominance {
-                        let dominance = locus.dominance_subset(&strain_ixs);
-                        regression_3n(traits, &genotypes, &dominance, false)
-                    } else {
-                        regression_2n(traits, &genotypes)
-                    }
-                }
-                Some(c) => {
-                    if dataset.dominance {
-                        panic!(
-                            "reaper: no composite regression for intercross"
-                        );
-                    } else {
-                        regression_3n(traits, &genotypes, &c, true)
-                    }
+impl Observed {
+    fn new(
+        dataset: &Dataset,
+        traits: &[Option<f64>],
+        std_errors: Option<&[Option<f64>]>,
+        strains: &[String],
+        covariates: Option<&Covariates>,
+    ) -> Result<Observed, Error> {
+        let mut values = Vec::with_capacity(traits.len());
+        let mut weights = Vec::with_capacity(traits.len());
+        let mut covariate_rows = Vec::with_capacity(traits.len());
+        let mut names = Vec::with_capacity(traits.len());
+
+        for (ix, (value, strain)) in traits.iter().zip(strains).enumerate() {
+            let value = match value {
+                Some(value) => *value,
+                None => continue,
+            };
+
+            let se = match std_errors {
+                None => None,
+                Some(se) => match se[ix] {
+                    Some(se) if se > 0.0 => Some(se),
+                    _ => continue,
+                },
+            };
+
+            // a strain without a row in the covariates file is more likely
+            // a misspelt name than a missing value
+            let row = match covariates {
+                None => Vec::new(),
+                Some(c) if !c.strains.contains(strain) => {
+                    return Err(Error::MissingCovariates {
+                        strain: strain.clone(),
+                    });
                 }
+                Some(c) => match c.strain_values(strain) {
+                    Some(row) => row,
+                    None => continue,
+                },
             };
 
-            result.push(QTL {
-                lrs: reg_result.lrs,
-                additive: reg_result.additive,
-                dominance: reg_result.dominance,
-                marker: locus.marker.clone(),
-            })
+            values.push(value);
+            weights.extend(se.map(|se| 1.0 / (se * se)));
+            covariate_rows.push(row);
+            names.push(strain.clone());
         }
+
+        let n_covariates = covariates.map_or(0, |c| c.columns.len());
+        let covariates = (0..n_covariates)
+            .map(|col| covariate_rows.iter().map(|row| row[col]).collect())
+            .collect();
+
+        Ok(Observed {
+            traits: values,
+            weights: std_errors.map(|_| weights),
+            covariates,
+            strain_ixs: dataset.strain_indices(&names)?,
+        })
     }
 
-    result
-}
+    // the observed strains of a trait, and the control markers of `model`
+    // for them, which every scan starts from
+    fn with_cofactors(
+        dataset: &Dataset,
+        traits: &[Option<f64>],
+        std_errors: Option<&[Option<f64>]>,
+        strains: &[String],
+        model: &Model,
+    ) -> Result<(Observed, Cofactors), Error> {
+        let observed = Observed::new(
+            dataset,
+            traits,
+            std_errors,
+            strains,
+            model.covariates.as_ref(),
+        )?;
+        let cofactors = Cofactors::new(dataset, model, &observed.strain_ixs)?;
+        Ok((observed, cofactors))
+    }
 
-pub fn permutation(
-    dataset: &Dataset,
-    traits: &[f64],
-    strains: &[String],
-    n_perms: usize,
-    threads: usize,
-) -> Vec<f64> {
-    let threads = threads.max(1);
-    // let lrs_thresh = -1.0;
-    // let top_n = 10;
-
-    let strain_ixs = dataset.strain_indices(strains);
-
-    let mut vecs = Vec::with_capacity(threads);
-    vecs.par_extend((0..threads).into_par_iter().map(|_| {
-        let mut temp_vec = Vec::with_capacity(n_perms / 4);
-        let mut p_traits = Vec::from(traits);
-        permuted_mut(&mut p_traits);
-        (0..(n_perms / threads)).for_each(|_| {
-            let mut lrs_max = 0.0;
-            let mut genotypes = vec![0.0; strain_ixs.len()];
-
-            for (_, loci) in dataset.genome.chromosomes.iter() {
-                for locus in loci.iter() {
-                    locus.genotypes_subindices(&strain_ixs, &mut genotypes);
-                    let reg_result = regression_2n(&p_traits, &genotypes);
-                    lrs_max = reg_result.lrs.max(lrs_max);
-                }
-            }
-            temp_vec.push(lrs_max);
+    fn weights(&self) -> Option<&[f64]> {
+        self.weights.as_ref().map(|w| &w[..])
+    }
+}
 
-            permuted_mut(&mut p_traits);
-        });
-        temp_vec.into_iter()
-    }));
-    let mut lrs_vec: Vec<_> = vecs.into_iter().flatten().collect();
+fn gather(values: &[f64], indices: &[usize], out: &mut [f64]) {
+    for (o, ix) in out.iter_mut().zip(indices) {
+        *o = values[*ix];
+    }
+}
 
-    lrs_vec.sort_by(|x, y| x.partial_cmp(y).unwrap());
-    lrs_vec
+/// The control markers of a model, with their genotypes (and dominance,
+/// for intercrosses) for the observed strains
+struct Cofactors {
+    window: Option<f64>,
+    markers: Vec<(Marker, Vec<Vec<f64>>)>,
 }
 
-pub fn bootstrap(
-    dataset: &Dataset,
-    traits: &[f64],
-    strains: &[String],
-    control: Option<&str>,
-    n_boot: usize,
-) -> Vec<usize> {
-    let strain_ixs = dataset.strain_indices(strains);
-    let n_loci = dataset.n_loci();
+impl Cofactors {
+    fn new(
+        dataset: &Dataset,
+        model: &Model,
+        strain_ixs: &[usize],
+    ) -> Result<Cofactors, Error> {
+        let markers = model
+            .control
+            .iter()
+            .map(|name| {
+                let locus = find_locus(dataset, name)?;
+                let columns = genotype_columns(dataset, locus, strain_ixs);
+                Ok((locus.marker.clone(), columns))
+            })
+            .collect::<Result<_, Error>>()?;
 
-    let n_test = n_boot.max(BOOTSTRAP_TESTSIZE).min(MAXPERMUTATION);
+        Ok(Cofactors {
+            window: model.window,
+            markers,
+        })
+    }
 
-    let mut locus_count = vec![0; n_loci];
+    /// The columns of the cofactors that are fitted when scanning
+    /// `markers`; a cofactor is left out if it is in the window of any
+    /// of them
+    fn columns(&self, markers: &[&Marker]) -> Vec<&[f64]> {
+        self.columns_where(&self.included(markers)).collect()
+    }
 
-    let control_geno: Option<Vec<_>> = control.map(|c| {
-        dataset
-            .genome
-            .find_locus(c)
-            .expect("Could not find control genotype")
-            .genotypes_subset(&strain_ixs)
-    });
+    // whether each cofactor is fitted when scanning `markers`
+    fn included(&self, markers: &[&Marker]) -> Vec<bool> {
+        let excluded = |cofactor: &Marker| match self.window {
+            None => false,
+            Some(window) => markers.iter().any(|marker| {
+                cofactor.chromosome == marker.chromosome
+                    && (cofactor.centi_morgan - marker.centi_morgan).abs()
+                        <= window
+            }),
+        };
+
+        self.markers
+            .iter()
+            .map(|(cofactor, _)| !excluded(cofactor))
+            .collect()
+    }
 
-    for _ in 0..n_test {
-        let indices = bootstrap_indices(traits);
-        let b_traits: Vec<_> =
-            indices.iter().cloned().map(|ix| traits[ix]).collect();
+    fn columns_where<'a, 'b>(
+        &'a self,
+        included: &'b [bool],
+    ) -> impl Iterator<Item = &'a [f64]> + 'b
+    where
+        'a: 'b,
+    {
+        self.markers
+            .iter()
+            .zip(included)
+            .filter(|(_, included)| **included)
+            .flat_map(|((_, columns), _)| columns.iter().map(|c| &c[..]))
+    }
 
-        let mut lrs_max = 0.0;
-        let mut l = 0;
-        let mut lrs_max_pos = 0;
+    // these cofactors followed by `loci`
+    fn with_loci(
+        &self,
+        dataset: &Dataset,
+        loci: &[&Locus],
+        strain_ixs: &[usize],
+    ) -> Cofactors {
+        let mut markers = self.markers.clone();
+        markers.extend(loci.iter().map(|locus| {
+            let columns = genotype_columns(dataset, locus, strain_ixs);
+            (locus.marker.clone(), columns)
+        }));
+
+        Cofactors {
+            window: self.window,
+            markers,
+        }
+    }
 
-        for (_, loci) in dataset.genome.chromosomes.iter() {
-            for locus in loci.iter() {
-                let genotypes = locus.genotypes_subset(&strain_ixs);
-                let b_genotypes: Vec<_> =
-                    indices.iter().cloned().map(|ix| genotypes[ix]).collect();
-
-                let reg_result = if let Some(control) = &control_geno {
-                    let b_control: Vec<_> =
-                        indices.iter().cloned().map(|ix| control[ix]).collect();
-                    regression_3n(&b_traits, &b_genotypes, &b_control, true)
-                } else {
-                    // TODO variance
-                    regression_2n(&b_traits, &b_genotypes)
-                };
+    fn resampled(&self, indices: &[usize]) -> Cofactors {
+        let markers = self
+            .markers
+            .iter()
+            .map(|(marker, columns)| {
+                let columns = columns
+                    .iter()
+                    .map(|c| indices.iter().map(|ix| c[*ix]).collect())
+                    .collect();
+                (marker.clone(), columns)
+            })
+            .collect();
 
-                if lrs_max < reg_result.lrs {
-                    lrs_max_pos = l;
-                    lrs_max = reg_result.lrs;
-                }
+        Cofactors {
+            window: self.window,
+            markers,
+        }
+    }
+}
+
+// Fits the model at a single locus, choosing the regression that matches
+// the locus dominance and the cofactors. A single cofactor in a riset uses
+// the closed-form composite regression from the original implementation.
+fn locus_regression(
+    traits: &[f64],
+    genotypes: &[f64],
+    dominance: Option<&[f64]>,
+    cofactors: &[&[f64]],
+    weights: Option<&[f64]>,
+) -> RegResult {
+    locus_regression_with(
+        traits,
+        genotypes,
+        dominance,
+        cofactors,
+        weights,
+        &mut Designs::default(),
+    )
+}
 
-                l += 1;
+// `locus_regression`, with the design matrices of a composite regression
+// in `designs`, so that a scan can reuse them from locus to locus
+fn locus_regression_with(
+    traits: &[f64],
+    genotypes: &[f64],
+    dominance: Option<&[f64]>,
+    cofactors: &[&[f64]],
+    weights: Option<&[f64]>,
+    designs: &mut Designs,
+) -> RegResult {
+    match (dominance, cofactors, weights) {
+        (None, [], None) => regression_2n(traits, genotypes),
+        (None, [], Some(w)) => regression_2n_variance(traits, genotypes, w),
+        (Some(d), [], None) => regression_3n(traits, genotypes, d, false),
+        (Some(d), [], Some(w)) => {
+            regression_3n_variance(traits, genotypes, d, w, false)
+        }
+        (None, [c], w) => {
+            let result = match w {
+                None => regression_3n(traits, genotypes, c, true),
+                Some(w) => {
+                    regression_3n_variance(traits, genotypes, c, w, true)
+                }
+            };
+            // `regression_3n` reports the control effect as dominance,
+            // which a riset does not have
+            RegResult {
+                dominance: None,
+                dominance_se: None,
+                ..result
             }
         }
-        locus_count[lrs_max_pos] += 1;
+        (d, c, w) => regression_composite(traits, genotypes, d, c, w, designs),
     }
-
-    locus_count
 }
 
 // `traits` corresponds to `YY`
@@ -234,23 +2100,30 @@ fn regression_2n(traits: &[f64], genotypes: &[f64]) -> RegResult {
         + b * (2.0 * a * sig_x + b * sig_xx - 2.0 * sig_xy);
 
     let mut lrs = n * (tss / rss).ln();
+    let df = n - 2.0;
+    let mut additive_se = std_error(rss, df, 1.0 / d);
 
     if lrs.is_nan() || lrs < 0.0 {
         b = 0.0;
         lrs = 0.0;
+        additive_se = f64::NAN;
     }
 
     RegResult {
         lrs,
         additive: b,
         dominance: None,
+        additive_se,
+        dominance_se: None,
+        df,
     }
 }
 
+// `weights` are the inverse variances of the traits
 fn regression_2n_variance(
     traits: &[f64],
     genotypes: &[f64],
-    variance: &[f64],
+    weights: &[f64],
 ) -> RegResult {
     let mut sig_yv = 0.0;
     let mut sig_yyv = 0.0;
@@ -263,7 +2136,7 @@ fn regression_2n_variance(
     let n_strains = traits.len();
 
     for ix in 0..traits.len() {
-        let temp0 = 1.0 / variance[ix];
+        let temp0 = weights[ix];
         let temp1 = traits[ix];
         let temp2 = genotypes[ix];
         sig_1v += temp0;
@@ -284,16 +2157,22 @@ fn regression_2n_variance(
         + a * (sig_1v * a - 2.0 * sig_yv)
         + b * (2.0 * a * sig_xv + b * sig_xxv - 2.0 * sig_xyv);
     let mut lrs = (n_strains as f64) * (tss / rss).ln();
+    let df = n_strains as f64 - 2.0;
+    let mut additive_se = std_error(rss, df, 1.0 / d);
 
     if lrs.is_nan() || lrs < 0.0 {
         b = 0.0;
         lrs = 0.0;
+        additive_se = f64::NAN;
     }
 
     RegResult {
         lrs,
         additive: b,
         dominance: None,
+        additive_se,
+        dominance_se: None,
+        df,
     }
 }
 
@@ -363,42 +2242,50 @@ fn regression_3n(
     };
 
     let mut lrs = n * (ssr / ssf).ln();
+    // `temp5 / temp6` and `temp3 / temp6` are the diagonal elements of
+    // the inverse of X'X for the locus and the control
+    let df = n - 3.0;
+    let mut additive_se = std_error(ssf, df, temp5 / temp6);
+    let mut dominance_se = std_error(ssf, df, temp3 / temp6);
     if lrs.is_nan() || lrs < 0.0 {
         betax = 0.0;
         lrs = 0.0;
         // NOTE: in the old implementation it is `betak`, not `betac`, that is set to 0.0 here, but `betak` is not used later, so I assume it's a mistake!
         betac = 0.0;
+        additive_se = f64::NAN;
+        dominance_se = f64::NAN;
     }
 
     RegResult {
         lrs,
         additive: betax,
         dominance: Some(betac),
+        additive_se,
+        dominance_se: Some(dominance_se),
+        df,
     }
 }
 
-// this one will require a bit more work since it actually uses matrices!
-/*
-fn regression_3n(
+// corresponds to the weighted branch of `_3n` in regression.c, which
+// inverts the 3x3 normal equations; `weights` are the inverse variances
+// of the traits
+fn regression_3n_variance(
     traits: &[f64],
     genotypes: &[f64],
     controls: &[f64],
-    variance: &[f64],
+    weights: &[f64],
     diff: bool,
 ) -> RegResult {
-
-
-    let mut sig1V = 0.0;
-    let mut sigYV = 0.0;
-    let mut sigXV =0.0;
-    let mut sigCV =0.0;
-    let mut sigXXV = 0.0;
-    let mut sigYYV = 0.0;
-    let mut sigCCV = 0.0;
-    let mut sigXYV = 0.0;
-    let mut sigXCV = 0.0;
-    let mut sigCYV = 0.0;
-
+    let mut sig_1v = 0.0;
+    let mut sig_yv = 0.0;
+    let mut sig_xv = 0.0;
+    let mut sig_cv = 0.0;
+    let mut sig_xxv = 0.0;
+    let mut sig_yyv = 0.0;
+    let mut sig_ccv = 0.0;
+    let mut sig_xyv = 0.0;
+    let mut sig_xcv = 0.0;
+    let mut sig_cyv = 0.0;
 
     let n_strains = traits.len();
     let n = n_strains as f64;
@@ -407,59 +2294,1481 @@ fn regression_3n(
         let c = controls[ix];
         let x = genotypes[ix];
         let y = traits[ix];
-        let v = 1.0/variance[ix];
-        sig1V += v;
-        sigYV += y*v;
-        sigXV += x*v;
-        sigCV += c*v;
-        sigXXV += x*x*v;
-        sigYYV += y*y*v;
-        sigCCV += c*c*v;
-        sigXYV += x*y*v;
-        sigXCV += x*c*v;
-        sigCYV += c*y*v;
-    }
-
-    aa = square(3);
-    aa[0][0] = sig1V;
-    aa[1][1] = sigXXV;
-    aa[2][2] = sigCCV;
-    aa[0][1] = aa[1][0] = sigXV;
-    aa[0][2] = aa[2][0] = sigCV;
-    aa[1][2] = aa[2][1] = sigXCV;
-
-    inverse(aa,3);
-
-    betak = aa[0][0]*sigYV + aa[0][1]*sigXYV + aa[0][2]*sigCYV;
-    betax = aa[1][0]*sigYV + aa[1][1]*sigXYV + aa[1][2]*sigCYV;
-    betac = aa[2][0]*sigYV + aa[2][1]*sigXYV + aa[2][2]*sigCYV;
-    ssf = sigYYV+betax*(betax*sigXXV-2*sigXYV)+betac*(betac*sigCCV-2*sigCYV)+ 2*betax*betac*sigXCV+betak*(sig1V*betak+2*betax*sigXV+2*betac*sigCV-2*sigYV);
-    if (diff != 0){
-        D = sigCCV - sigCV*sigCV/sig1V;
-        a = (sigCCV*sigYV - sigCV*sigCYV)/(sig1V*D);
-        b = (sigCYV - (sigCV*sigYV/sig1V))/D;
-        ssr = sigYYV + a*(sig1V*a-2.0*sigYV) + b*(2.0*a*sigCV+b*sigCCV-2.0*sigCYV);
-    }
-    else
-        ssr = sigYYV - (sigYV*sigYV)/sig1V;
-
-    LRS = n*log(ssr/ssf);
-    if (isnan(LRS) || (LRS < 0)){
+        let v = weights[ix];
+        sig_1v += v;
+        sig_yv += y * v;
+        sig_xv += x * v;
+        sig_cv += c * v;
+        sig_xxv += x * x * v;
+        sig_yyv += y * y * v;
+        sig_ccv += c * c * v;
+        sig_xyv += x * y * v;
+        sig_xcv += x * c * v;
+        sig_cyv += c * y * v;
+    }
+
+    let aa = array![
+        [sig_1v, sig_xv, sig_cv],
+        [sig_xv, sig_xxv, sig_xcv],
+        [sig_cv, sig_xcv, sig_ccv],
+    ];
+
+    let aa = match linalg::inverse(&aa) {
+        Some(inv) => inv,
+        None => {
+            return RegResult {
+                lrs: 0.0,
+                additive: 0.0,
+                dominance: Some(0.0),
+                additive_se: f64::NAN,
+                dominance_se: Some(f64::NAN),
+                df: n - 3.0,
+            }
+        }
+    };
+
+    let betak =
+        aa[[0, 0]] * sig_yv + aa[[0, 1]] * sig_xyv + aa[[0, 2]] * sig_cyv;
+    let mut betax =
+        aa[[1, 0]] * sig_yv + aa[[1, 1]] * sig_xyv + aa[[1, 2]] * sig_cyv;
+    let mut betac =
+        aa[[2, 0]] * sig_yv + aa[[2, 1]] * sig_xyv + aa[[2, 2]] * sig_cyv;
+
+    let ssf = sig_yyv
+        + betax * (betax * sig_xxv - 2.0 * sig_xyv)
+        + betac * (betac * sig_ccv - 2.0 * sig_cyv)
+        + 2.0 * betax * betac * sig_xcv
+        + betak
+            * (sig_1v * betak + 2.0 * betax * sig_xv + 2.0 * betac * sig_cv
+                - 2.0 * sig_yv);
+
+    let ssr = if diff {
+        let d = sig_ccv - sig_cv * sig_cv / sig_1v;
+        let a = (sig_ccv * sig_yv - sig_cv * sig_cyv) / (sig_1v * d);
+        let b = (sig_cyv - (sig_cv * sig_yv / sig_1v)) / d;
+        sig_yyv
+            + a * (sig_1v * a - 2.0 * sig_yv)
+            + b * (2.0 * a * sig_cv + b * sig_ccv - 2.0 * sig_cyv)
+    } else {
+        sig_yyv - (sig_yv * sig_yv) / sig_1v
+    };
+
+    let mut lrs = n * (ssr / ssf).ln();
+    let df = n - 3.0;
+    let mut additive_se = std_error(ssf, df, aa[[1, 1]]);
+    let mut dominance_se = std_error(ssf, df, aa[[2, 2]]);
+    if lrs.is_nan() || lrs < 0.0 {
         betax = 0.0;
         betac = 0.0;
-        LRS = 0.0;
+        lrs = 0.0;
+        additive_se = f64::NAN;
+        dominance_se = f64::NAN;
     }
-    result[0] = LRS;
-    result[1] = betax;
-    result[2] = betac;
-    freesquare(aa,3);
-    return 1;
 
     RegResult {
         lrs,
         additive: betax,
         dominance: Some(betac),
+        additive_se,
+        dominance_se: Some(dominance_se),
+        df,
+    }
+}
+
+// the design matrices of the null and full models of a composite
+// regression, and their products with the weights of a weighted fit
+#[derive(Default)]
+struct Designs {
+    null: Array2<f64>,
+    full: Array2<f64>,
+    weighted_null: Array2<f64>,
+    weighted_full: Array2<f64>,
+}
+
+// composite regression with any number of cofactor columns, which make up
+// the null model together with the intercept; the additive (and, for
+// intercrosses, dominance) effects of the locus are fitted on top of them.
+// The design matrices are filled in `designs`, so that a scan can reuse
+// them from locus to locus.
+fn regression_composite(
+    traits: &[f64],
+    genotypes: &[f64],
+    dominance: Option<&[f64]>,
+    cofactors: &[&[f64]],
+    weights: Option<&[f64]>,
+    designs: &mut Designs,
+) -> RegResult {
+    let n = traits.len();
+
+    let mut columns = vec![genotypes];
+    columns.extend(dominance);
+    let n_locus = columns.len();
+    columns.extend_from_slice(cofactors);
+
+    linalg::fill_design_matrix(&mut designs.null, n, cofactors);
+    linalg::fill_design_matrix(&mut designs.full, n, &columns);
+
+    let fits = (
+        linalg::least_squares_with(
+            traits,
+            &designs.null,
+            weights,
+            &mut designs.weighted_null,
+        ),
+        linalg::least_squares_with(
+            traits,
+            &designs.full,
+            weights,
+            &mut designs.weighted_full,
+        ),
+    );
+
+    let df = n as f64 - columns.len() as f64 - 1.0;
+
+    if let (Some(null), Some(full)) = fits {
+        let lrs = (n as f64) * (null.rss / full.rss).ln();
+        if !lrs.is_nan() && lrs >= 0.0 {
+            let se =
+                |ix: usize| std_error(full.rss, df, full.inverse[[ix, ix]]);
+            return RegResult {
+                lrs,
+                additive: full.coefficients[1],
+                dominance: dominance.map(|_| full.coefficients[n_locus]),
+                additive_se: se(1),
+                dominance_se: dominance.map(|_| se(n_locus)),
+                df,
+            };
+        }
+    }
+
+    RegResult {
+        lrs: 0.0,
+        additive: 0.0,
+        dominance: dominance.map(|_| 0.0),
+        additive_se: f64::NAN,
+        dominance_se: dominance.map(|_| f64::NAN),
+        df,
+    }
+}
+
+// the LRS of the full model with genotype-by-covariate interaction terms,
+// against the null model of the cofactors alone; `interactive` are the
+// cofactor columns that interact with the locus
+fn regression_interaction(
+    traits: &[f64],
+    genotypes: &[f64],
+    dominance: Option<&[f64]>,
+    cofactors: &[&[f64]],
+    interactive: &[&[f64]],
+    weights: Option<&[f64]>,
+) -> f64 {
+    let n = traits.len();
+
+    let mut locus = vec![genotypes];
+    locus.extend(dominance);
+
+    let products: Vec<Vec<f64>> = locus
+        .iter()
+        .flat_map(|l| {
+            interactive.iter().map(move |c| {
+                l.iter().zip(c.iter()).map(|(x, c)| x * c).collect()
+            })
+        })
+        .collect();
+
+    let mut columns = locus;
+    columns.extend_from_slice(cofactors);
+    columns.extend(products.iter().map(|p| &p[..]));
+
+    let null = linalg::design_matrix(n, cofactors);
+    let full = linalg::design_matrix(n, &columns);
+
+    let fits = (
+        linalg::least_squares(traits, &null, weights),
+        linalg::least_squares(traits, &full, weights),
+    );
+
+    match fits {
+        (Some(null), Some(full)) => {
+            let lrs = (n as f64) * (null.rss / full.rss).ln();
+            if lrs.is_nan() || lrs < 0.0 {
+                0.0
+            } else {
+                lrs
+            }
+        }
+        _ => 0.0,
+    }
+}
+
+// the fits of two loci without and with their interaction terms, on top
+// of the cofactors. The design matrices hold the intercept, `first`,
+// `second` and `cofactors`, followed in the full model by the product of
+// each column of `first` with each column of `second`.
+fn pair_fits(
+    traits: &[f64],
+    first: &[&[f64]],
+    second: &[&[f64]],
+    cofactors: &[&[f64]],
+    weights: Option<&[f64]>,
+) -> (Option<linalg::Fit>, Option<linalg::Fit>) {
+    let n = traits.len();
+
+    let products: Vec<Vec<f64>> = first
+        .iter()
+        .flat_map(|a| {
+            second.iter().map(move |b| {
+                a.iter().zip(b.iter()).map(|(a, b)| a * b).collect()
+            })
+        })
+        .collect();
+
+    let mut columns = first.to_vec();
+    columns.extend_from_slice(second);
+    columns.extend_from_slice(cofactors);
+
+    let additive = linalg::least_squares(
+        traits,
+        &linalg::design_matrix(n, &columns),
+        weights,
+    );
+    columns.extend(products.iter().map(|p| &p[..]));
+    let full = linalg::least_squares(
+        traits,
+        &linalg::design_matrix(n, &columns),
+        weights,
+    );
+
+    (additive, full)
+}
+
+// the LRS of two loci with and without their interaction terms, against
+// the null model of the cofactors alone; each locus is given as its
+// genotype (and dominance) columns
+fn regression_pair(
+    traits: &[f64],
+    first: &[&[f64]],
+    second: &[&[f64]],
+    cofactors: &[&[f64]],
+    weights: Option<&[f64]>,
+) -> (f64, f64) {
+    let n = traits.len();
+
+    let null = linalg::least_squares(
+        traits,
+        &linalg::design_matrix(n, cofactors),
+        weights,
+    );
+    let (additive, full) = pair_fits(traits, first, second, cofactors, weights);
+
+    let null_rss = match null {
+        Some(null) => null.rss,
+        None => return (0.0, 0.0),
+    };
+    let lrs = |fit: linalg::Fit| {
+        let lrs = (n as f64) * (null_rss / fit.rss).ln();
+        if lrs.is_nan() || lrs < 0.0 {
+            0.0
+        } else {
+            lrs
+        }
+    };
+
+    let additive = additive.map_or(0.0, lrs);
+    // without strains in enough genotype combinations to fit the
+    // interaction, the full model is the additive one
+    let full = full.map_or(additive, lrs);
+
+    (full, additive)
+}
+
+// the interaction of a scanned locus with a fixed one, against the model
+// with both loci
+fn regression_epistasis(
+    traits: &[f64],
+    scanned: &[&[f64]],
+    fixed: &[&[f64]],
+    cofactors: &[&[f64]],
+    weights: Option<&[f64]>,
+) -> EpistasisResult {
+    let n = traits.len();
+    let dominance = scanned.len() > 1;
+
+    if let (Some(additive), Some(full)) =
+        pair_fits(traits, scanned, fixed, cofactors, weights)
+    {
+        let lrs = (n as f64) * (additive.rss / full.rss).ln();
+        if !lrs.is_nan() && lrs >= 0.0 {
+            // the product of the two additive columns
+            let ix = 1 + scanned.len() + fixed.len() + cofactors.len();
+            return EpistasisResult {
+                lrs,
+                additive: full.coefficients[1],
+                dominance: if dominance {
+                    Some(full.coefficients[2])
+                } else {
+                    None
+                },
+                interaction: full.coefficients[ix],
+            };
+        }
+    }
+
+    EpistasisResult {
+        lrs: 0.0,
+        additive: 0.0,
+        dominance: if dominance { Some(0.0) } else { None },
+        interaction: 0.0,
     }
 }
 
-*/
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geneobject::Traits;
+    use std::path::Path;
+
+    // a small backcross shared by the regression kernel tests
+    const TRAITS: [f64; 8] = [6.82, 6.73, 6.53, 6.83, 6.84, 6.96, 6.70, 6.77];
+    const GENOTYPES: [f64; 8] = [-1.0, -1.0, 1.0, 1.0, 1.0, -1.0, 1.0, 0.5];
+    const CONTROLS: [f64; 8] = [1.0, -1.0, 1.0, -1.0, 1.0, -1.0, -1.0, 1.0];
+
+    // residuals of the simulated traits of ten strains
+    const NOISE: [f64; 10] =
+        [0.01, -0.02, 0.0, 0.02, -0.01, 0.01, 0.0, -0.01, 0.02, 0.0];
+
+    // the genotype file `genotypes` and the traits of the test data
+    fn fixture(genotypes: &str) -> (Dataset, Traits) {
+        let input =
+            Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/data/input");
+        let dataset = Dataset::read_file(&input.join(genotypes)).unwrap();
+        let traits = Traits::read_file(&input.join("trait.txt")).unwrap();
+        (dataset, traits)
+    }
+
+    // a linear congruential generator for simulated data, which unlike
+    // `StdRng` does not depend on the version of `rand`
+    struct Lcg(u64);
+
+    impl Lcg {
+        fn new(seed: u64) -> Lcg {
+            let mut rng = Lcg(0);
+            rng.0 = rng.step(seed);
+            rng
+        }
+
+        fn step(&self, state: u64) -> u64 {
+            state
+                .wrapping_mul(6_364_136_223_846_793_005)
+                .wrapping_add(1_442_695_040_888_963_407)
+        }
+
+        // uniform in [0, 1)
+        fn uniform(&mut self) -> f64 {
+            self.0 = self.step(self.0);
+            (self.0 >> 33) as f64 / (1u64 << 31) as f64
+        }
+
+        // roughly normal, with mean 0 and variance 1/3
+        fn noise(&mut self) -> f64 {
+            (0..4).map(|_| self.uniform()).sum::<f64>() - 2.0
+        }
+    }
+
+    // An intercross of `n` strains with three chromosomes of markers at
+    // 0, 20, 40, 45 and 60 cM, named `M<chr>_<ix>`, with one crossover
+    // per 100 cM. Returns the dataset, its strains and the additive
+    // genotypes of the markers in genome order.
+    fn simulated_intercross(
+        name: &str,
+        n: usize,
+        rng: &mut Lcg,
+    ) -> (Dataset, Vec<String>, Vec<Vec<f64>>) {
+        let strains: Vec<String> =
+            (0..n).map(|ix| format!("S{}", ix)).collect();
+        let mut text = String::from(
+            "@type:intercross\n@name:F2\n@mat:B\n@pat:D\nChr\tLocus\tcM",
+        );
+        for strain in strains.iter() {
+            text += &format!("\t{}", strain);
+        }
+        text += "\n";
+
+        let centi_morgans = [0.0, 20.0, 40.0, 45.0, 60.0];
+        let mut genotypes = Vec::new();
+        for chr in 1..=3 {
+            let mut draw = || if rng.uniform() < 0.5 { -1.0 } else { 1.0 };
+            let mut haplotypes: Vec<[f64; 2]> =
+                (0..n).map(|_| [draw(), draw()]).collect();
+
+            for (ix, cm) in centi_morgans.iter().enumerate() {
+                if ix > 0 {
+                    let r = (cm - centi_morgans[ix - 1]) / 100.0;
+                    for h in haplotypes.iter_mut().flatten() {
+                        if rng.uniform() < r {
+                            *h = -*h;
+                        }
+                    }
+                }
+
+                let additive: Vec<f64> =
+                    haplotypes.iter().map(|h| (h[0] + h[1]) / 2.0).collect();
+                text += &format!("{}\tM{}_{}\t{}", chr, chr, ix, cm);
+                for a in additive.iter() {
+                    text += match *a {
+                        a if a < 0.0 => "\tB",
+                        a if a > 0.0 => "\tD",
+                        _ => "\tH",
+                    };
+                }
+                text += "\n";
+                genotypes.push(additive);
+            }
+        }
+
+        let path = std::env::temp_dir().join(format!(
+            "qtlreaper-{}-{}.geno",
+            name,
+            std::process::id()
+        ));
+        std::fs::write(&path, text).unwrap();
+        let dataset = Dataset::read_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        (dataset, strains, genotypes)
+    }
+
+    #[test]
+    fn equal_variances_give_unweighted_regression() {
+        let weights = [4.0; 8];
+
+        let unweighted = regression_2n(&TRAITS, &GENOTYPES);
+        let weighted = regression_2n_variance(&TRAITS, &GENOTYPES, &weights);
+
+        assert!((unweighted.lrs - weighted.lrs).abs() < 1e-9);
+        assert!((unweighted.additive - weighted.additive).abs() < 1e-9);
+    }
+
+    #[test]
+    fn equal_variances_give_unweighted_composite_regression() {
+        let weights = [4.0; 8];
+
+        for &diff in [true, false].iter() {
+            let unweighted =
+                regression_3n(&TRAITS, &GENOTYPES, &CONTROLS, diff);
+            let weighted = regression_3n_variance(
+                &TRAITS, &GENOTYPES, &CONTROLS, &weights, diff,
+            );
+
+            assert!((unweighted.lrs - weighted.lrs).abs() < 1e-9);
+            assert!((unweighted.additive - weighted.additive).abs() < 1e-9);
+            assert!(
+                (unweighted.dominance.unwrap() - weighted.dominance.unwrap())
+                    .abs()
+                    < 1e-9
+            );
+        }
+    }
+
+    #[test]
+    fn intercross_composite_regression_finds_locus_effects() {
+        let genotypes = [-1.0, 0.0, 1.0, -1.0, 0.0, 1.0, -1.0, 0.0, 1.0, 1.0];
+        let dominance = [0.0, 1.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0, 0.0, 0.0];
+        let controls = [1.0, 1.0, -1.0, 0.0, -1.0, 0.0, 1.0, 0.0, 1.0, -1.0];
+        let c_dominance = [0.0, 0.0, 0.0, 1.0, 0.0, 1.0, 0.0, 1.0, 0.0, 0.0];
+
+        let traits: Vec<_> = (0..10)
+            .map(|i| {
+                1.0 + 2.0 * genotypes[i] + 0.5 * dominance[i]
+                    - 0.3 * controls[i]
+                    + NOISE[i]
+            })
+            .collect();
+
+        let result = regression_composite(
+            &traits,
+            &genotypes,
+            Some(&dominance),
+            &[&controls, &c_dominance],
+            None,
+            &mut Designs::default(),
+        );
+
+        assert!(result.lrs > 0.0);
+        assert!((result.additive - 2.0).abs() < 0.05);
+        assert!((result.dominance.unwrap() - 0.5).abs() < 0.05);
+    }
+
+    #[test]
+    fn composite_regression_matches_closed_form() {
+        let closed = regression_3n(&TRAITS, &GENOTYPES, &CONTROLS, true);
+        let general = regression_composite(
+            &TRAITS,
+            &GENOTYPES,
+            None,
+            &[&CONTROLS],
+            None,
+            &mut Designs::default(),
+        );
+
+        assert!((closed.lrs - general.lrs).abs() < 1e-9);
+        assert!((closed.additive - general.additive).abs() < 1e-9);
+    }
+
+    #[test]
+    fn closed_form_standard_errors_match_least_squares() {
+        let genotypes = [-1.0, -1.0, 1.0, 1.0, 0.0, -1.0, 1.0, 0.0];
+        let dominance = [0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 1.0];
+        let weights = [4.0, 2.0, 4.0, 10.0, 3.0, 4.0, 5.0, 2.5];
+
+        let close = |closed: RegResult, general: RegResult| {
+            assert!((closed.additive_se - general.additive_se).abs() < 1e-9);
+            if let (Some(c), Some(g)) =
+                (closed.dominance_se, general.dominance_se)
+            {
+                assert!((c - g).abs() < 1e-9);
+            }
+            assert_eq!(closed.df, general.df);
+            assert!(closed.additive_se > 0.0);
+        };
+
+        close(
+            regression_2n(&TRAITS, &genotypes),
+            regression_composite(
+                &TRAITS,
+                &genotypes,
+                None,
+                &[],
+                None,
+                &mut Designs::default(),
+            ),
+        );
+        close(
+            regression_2n_variance(&TRAITS, &genotypes, &weights),
+            regression_composite(
+                &TRAITS,
+                &genotypes,
+                None,
+                &[],
+                Some(&weights),
+                &mut Designs::default(),
+            ),
+        );
+        close(
+            regression_3n(&TRAITS, &genotypes, &dominance, false),
+            regression_composite(
+                &TRAITS,
+                &genotypes,
+                Some(&dominance),
+                &[],
+                None,
+                &mut Designs::default(),
+            ),
+        );
+        close(
+            regression_3n_variance(
+                &TRAITS, &genotypes, &dominance, &weights, false,
+            ),
+            regression_composite(
+                &TRAITS,
+                &genotypes,
+                Some(&dominance),
+                &[],
+                Some(&weights),
+                &mut Designs::default(),
+            ),
+        );
+    }
+
+    #[test]
+    fn interaction_regression_finds_interaction_effects() {
+        let genotypes = [-1.0, 1.0, -1.0, 1.0, -1.0, 1.0, -1.0, 1.0, 1.0, -1.0];
+        let sex = [0.0, 0.0, 1.0, 1.0, 0.0, 0.0, 1.0, 1.0, 0.0, 1.0];
+
+        // the locus only has an effect in one sex
+        let traits: Vec<_> = (0..10)
+            .map(|i| 1.0 + 0.5 * sex[i] + genotypes[i] * sex[i] + NOISE[i])
+            .collect();
+
+        let additive = regression_composite(
+            &traits,
+            &genotypes,
+            None,
+            &[&sex],
+            None,
+            &mut Designs::default(),
+        );
+        let lrs_full = regression_interaction(
+            &traits,
+            &genotypes,
+            None,
+            &[&sex],
+            &[&sex],
+            None,
+        );
+
+        assert!(lrs_full > additive.lrs);
+        assert!(lrs_full - additive.lrs > 10.0);
+    }
+
+    #[test]
+    fn pair_regression_finds_epistasis() {
+        let first = [-1.0, -1.0, 1.0, 1.0, -1.0, -1.0, 1.0, 1.0, -1.0, 1.0];
+        let second = [-1.0, 1.0, -1.0, 1.0, -1.0, 1.0, -1.0, 1.0, 1.0, -1.0];
+
+        // neither locus has an effect on its own
+        let traits: Vec<_> =
+            (0..10).map(|i| first[i] * second[i] + NOISE[i]).collect();
+
+        let (full, additive) =
+            regression_pair(&traits, &[&first], &[&second], &[], None);
+
+        assert!(additive < 1.0);
+        assert!(full - additive > 10.0);
+    }
+
+    #[test]
+    fn epistasis_regression_finds_interaction_effect() {
+        let scanned = [-1.0, -1.0, 1.0, 1.0, -1.0, -1.0, 1.0, 1.0, -1.0, 1.0];
+        let fixed = [-1.0, 1.0, -1.0, 1.0, -1.0, 1.0, -1.0, 1.0, 1.0, -1.0];
+
+        let traits: Vec<_> = (0..10)
+            .map(|i| {
+                2.0 * fixed[i]
+                    + 0.5 * scanned[i]
+                    + scanned[i] * fixed[i]
+                    + NOISE[i]
+            })
+            .collect();
+
+        let result =
+            regression_epistasis(&traits, &[&scanned], &[&fixed], &[], None);
+
+        assert!(result.lrs > 10.0);
+        assert!((result.additive - 0.5).abs() < 0.05);
+        assert!((result.interaction - 1.0).abs() < 0.05);
+        assert_eq!(None, result.dominance);
+    }
+
+    #[test]
+    fn early_stop_cuts_at_the_top_nth_exceedance() {
+        // the maximum of each stream, exceeding 8 first at streams 3, 6 and 14
+        let stream_max = |stream: usize| (stream * 7 % 11) as f64;
+        let maxima = |streams: Range<usize>| -> Vec<f64> {
+            streams.map(stream_max).collect()
+        };
+        let exceeding: Vec<_> =
+            (0..40).filter(|s| stream_max(*s) > 8.0).collect();
+        assert_eq!(&exceeding[..3], &[3, 6, 14]);
+
+        let stop = EarlyStop { lrs: 8.0, top_n: 3 };
+        let mut expected: Vec<_> = (0..15).map(stream_max).collect();
+        expected.sort_by(|x, y| x.partial_cmp(y).unwrap());
+
+        for round_size in &[1, 3, 4, 15, 64] {
+            let lrs = run_permutations(40, *round_size, Some(stop), maxima);
+            assert_eq!(expected, lrs, "round size {}", round_size);
+        }
+
+        // without enough exceedances all the permutations are run
+        let stop = EarlyStop {
+            lrs: 8.0,
+            top_n: 10,
+        };
+        assert_eq!(40, run_permutations(40, 3, Some(stop), maxima).len());
+        assert_eq!(40, run_permutations(40, 3, None, maxima).len());
+    }
+
+    #[test]
+    fn early_stopped_permutations_have_no_thresholds() {
+        let (dataset, traits) = fixture("BXD.txt");
+        let permutations = |early_stop| {
+            permutation(
+                &dataset,
+                &traits.traits[0].1,
+                None,
+                &traits.strains,
+                &Model::default(),
+                100,
+                5,
+                early_stop,
+                &[0.05],
+            )
+            .unwrap()
+        };
+
+        let full = permutations(None);
+        assert_eq!(100, full.lrs.len());
+        assert!(full.thresholds.is_some());
+        assert!(full.gev.is_some());
+
+        // every maximum exceeds an LRS of zero
+        let stopped = permutations(Some(EarlyStop {
+            lrs: 0.0,
+            top_n: 10,
+        }));
+        assert_eq!(10, stopped.lrs.len());
+        assert!(stopped.thresholds.is_none());
+        assert!(stopped.gev.is_none());
+        assert_eq!(None, stopped.pvalue_gev(10.0));
+    }
+
+    #[test]
+    fn thresholds_are_permutation_quantiles() {
+        let permutations: Vec<_> = (1..=100).map(f64::from).collect();
+
+        assert_eq!(95.0, threshold(&permutations, 0.05));
+        assert_eq!(37.0, threshold(&permutations, 0.63));
+        assert_eq!(100.0, threshold(&permutations, 0.0));
+        assert_eq!(0.0, threshold(&[], 0.05));
+
+        let thresholds = Thresholds::new(&permutations, &[0.05, 0.01]);
+        assert_eq!(37.0, thresholds.suggestive.lrs);
+        assert_eq!(95.0, thresholds.significant[0].lrs);
+        assert_eq!(99.0, thresholds.significant[1].lrs);
+        assert_eq!(0.01, thresholds.significant[1].alpha);
+    }
+
+    #[test]
+    fn permutations_only_depend_on_seed_domain_and_stream() {
+        let mut first = vec![0; 20];
+        let mut second = vec![0; 20];
+
+        permuted_mut(&mut first, &mut stream_rng(7, Domain::Permutation, 3));
+        // the previous contents of `order` do not matter
+        second.reverse();
+        permuted_mut(&mut second, &mut stream_rng(7, Domain::Permutation, 3));
+        assert_eq!(first, second);
+
+        permuted_mut(&mut second, &mut stream_rng(7, Domain::Permutation, 4));
+        assert_ne!(first, second);
+
+        // other scans and other traits draw other streams
+        permuted_mut(
+            &mut second,
+            &mut stream_rng(7, Domain::ScantwoPermutation, 3),
+        );
+        assert_ne!(first, second);
+        permuted_mut(
+            &mut second,
+            &mut stream_rng(trait_seed(7, 1), Domain::Permutation, 3),
+        );
+        assert_ne!(first, second);
+
+        second.sort();
+        assert_eq!((0..20).collect::<Vec<_>>(), second);
+    }
+
+    #[test]
+    fn permutations_do_not_depend_on_the_number_of_threads() {
+        let (dataset, traits) = fixture("BXD.txt");
+        let values = &traits.traits[0].1;
+        // weights take the per-locus path rather than the batched one
+        let std_errors: Vec<_> = (0..values.len())
+            .map(|ix| Some(0.2 + 0.1 * (ix % 3) as f64))
+            .collect();
+        let model = Model::default();
+
+        let permutations = |n_threads: usize, early_stop| {
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(n_threads)
+                .build()
+                .unwrap()
+                .install(|| {
+                    permutation(
+                        &dataset,
+                        values,
+                        Some(&std_errors),
+                        &traits.strains,
+                        &model,
+                        50,
+                        3,
+                        early_stop,
+                        &[],
+                    )
+                    .unwrap()
+                    .lrs
+                })
+        };
+
+        let single = permutations(1, None);
+        assert_eq!(50, single.len());
+        let stop = EarlyStop {
+            lrs: single[40],
+            top_n: 5,
+        };
+        let single_stopped = permutations(1, Some(stop));
+        assert!(single_stopped.len() < 50);
+
+        for n_threads in &[2, 3, 8] {
+            assert_eq!(single, permutations(*n_threads, None));
+            assert_eq!(single_stopped, permutations(*n_threads, Some(stop)));
+        }
+    }
+
+    #[test]
+    fn batched_permutations_match_locus_regression() {
+        let (dataset, traits) = fixture("BXD.txt");
+        let values = &traits.traits[0].1;
+
+        let models = [
+            Model::default(),
+            Model {
+                control: vec!["D1Mit294".to_string()],
+                window: Some(10.0),
+                ..Model::default()
+            },
+        ];
+
+        for model in models.iter() {
+            let batched = batched_permutation_maxima(
+                &dataset,
+                values,
+                &traits.strains,
+                model,
+                100,
+                42,
+                None,
+            )
+            .unwrap();
+            let per_locus = permutation_maxima(
+                &dataset,
+                values,
+                None,
+                &traits.strains,
+                model,
+                100,
+                42,
+                Domain::Permutation,
+                None,
+                |scan| {
+                    locus_regression(
+                        scan.traits,
+                        scan.genotypes,
+                        scan.dominance,
+                        &scan.columns,
+                        scan.weights,
+                    )
+                    .lrs
+                },
+            )
+            .unwrap();
+
+            assert_eq!(batched.len(), per_locus.len());
+            for (b, p) in batched.iter().zip(per_locus.iter()) {
+                assert!((b - p).abs() < 1e-6, "{} != {}", b, p);
+            }
+        }
+    }
+
+    #[test]
+    fn bootstrap_interval_covers_the_top_peak() {
+        let (dataset, traits) = fixture("BXD.txt");
+        let (_, values) = &traits.traits[3];
+
+        let model = Model::default();
+        let result =
+            bootstrap(&dataset, values, None, &traits.strains, &model, 100, 7)
+                .unwrap();
+
+        assert_eq!(dataset.n_loci(), result.counts.len());
+        let total: usize = result.counts.iter().map(|(_, c)| c).sum();
+        assert_eq!(100, total);
+
+        // T4 peaks at D1Mit155
+        let interval = result.interval.unwrap();
+        assert_eq!("1", interval.chromosome);
+        assert!(interval.start.centi_morgan <= 112.0);
+        assert!(interval.end.centi_morgan >= 112.0);
+    }
+
+    #[test]
+    fn intercross_bootstrap_uses_the_dominance_model() {
+        let mut rng = Lcg::new(13);
+        let (dataset, strains, genotypes) =
+            simulated_intercross("bootstrap", 100, &mut rng);
+        // heterozygotes at M2_2 differ from both homozygotes, which have
+        // the same mean, so the locus only has a dominance effect
+        let values: Vec<_> = genotypes[7]
+            .iter()
+            .map(|g| {
+                let dominance = if *g == 0.0 { 1.0 } else { 0.0 };
+                Some(dominance + 0.3 * rng.noise())
+            })
+            .collect();
+
+        let n_boot = 50;
+        let result = bootstrap(
+            &dataset,
+            &values,
+            None,
+            &strains,
+            &Model::default(),
+            n_boot,
+            7,
+        )
+        .unwrap();
+
+        assert_eq!("2", result.interval.unwrap().chromosome);
+        let on_second: usize = result
+            .counts
+            .iter()
+            .filter(|(marker, _)| marker.chromosome == "2")
+            .map(|(_, count)| count)
+            .sum();
+        assert!(on_second >= n_boot * 9 / 10, "{} samples", on_second);
+    }
+
+    #[test]
+    fn bootstrap_of_a_genome_without_loci_is_empty() {
+        let strains: Vec<String> =
+            (0..6).map(|ix| format!("S{}", ix)).collect();
+        let text = format!(
+            "@type:riset\n@name:BXD\n@mat:B\n@pat:D\nChr\tLocus\tcM\t{}\n",
+            strains.join("\t")
+        );
+        let path = std::env::temp_dir()
+            .join(format!("qtlreaper-empty-{}.geno", std::process::id()));
+        std::fs::write(&path, text).unwrap();
+        let dataset = Dataset::read_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(0, dataset.n_loci());
+
+        let values: Vec<_> = TRAITS[..6].iter().cloned().map(Some).collect();
+        let result = bootstrap(
+            &dataset,
+            &values,
+            None,
+            &strains,
+            &Model::default(),
+            10,
+            7,
+        )
+        .unwrap();
+        assert_eq!(10, result.n_samples);
+        assert!(result.counts.is_empty());
+        assert!(result.interval.is_none());
+    }
+
+    #[test]
+    fn support_intervals_surround_each_peak() {
+        let qtl =
+            |name: &str, chromosome: &str, centi_morgan: f64, lod: f64| {
+                let marker = Marker {
+                    name: name.to_string(),
+                    centi_morgan,
+                    mega_basepair: None,
+                    chromosome: chromosome.to_string(),
+                };
+                QTL::new(marker, lod * 2.0 * 10f64.ln(), 0.0, None, 0.0)
+            };
+
+        let qtls = vec![
+            qtl("A", "1", 0.0, 0.5),
+            qtl("B", "1", 10.0, 2.0),
+            qtl(" - ", "1", 20.0, 4.0),
+            qtl("D", "1", 30.0, 5.0),
+            qtl("E", "1", 40.0, 3.0),
+            qtl("F", "1", 50.0, 0.2),
+            qtl("G", "2", 5.0, 1.0),
+        ];
+
+        let peaks = support_intervals(&qtls, 1.5, 0.95);
+        assert_eq!(2, peaks.len());
+
+        // the interval starts at the pseudo-marker at 20 cM, and is
+        // widened to the marker before it
+        assert_eq!("D", peaks[0].peak.name);
+        assert!((peaks[0].lod - 5.0).abs() < 1e-9);
+        assert_eq!("B", peaks[0].lod_drop.start.name);
+        assert_eq!("D", peaks[0].lod_drop.end.name);
+        assert_eq!("B", peaks[0].bayes.start.name);
+        assert_eq!("D", peaks[0].bayes.end.name);
+
+        assert_eq!("G", peaks[1].peak.name);
+        assert_eq!("G", peaks[1].lod_drop.start.name);
+        assert_eq!("G", peaks[1].bayes.end.name);
+    }
+
+    #[test]
+    fn bayes_intervals_take_the_loci_of_highest_density() {
+        let qtl = |name: &str, centi_morgan: f64, lod: f64| {
+            let marker = Marker {
+                name: name.to_string(),
+                centi_morgan,
+                mega_basepair: None,
+                chromosome: "1".to_string(),
+            };
+            QTL::new(marker, lod * 2.0 * 10f64.ln(), 0.0, None, 0.0)
+        };
+
+        // E has a lower density than B and C, but stands for far more
+        // of the chromosome
+        let qtls = vec![
+            qtl("A", 0.0, 0.0),
+            qtl("B", 10.0, 3.0),
+            qtl("C", 11.0, 2.95),
+            qtl("D", 12.0, 2.9),
+            qtl("E", 100.0, 2.0),
+            qtl("F", 190.0, 0.0),
+        ];
+
+        // B, C and D hold 82% of the area
+        let peaks = support_intervals(&qtls, 1.5, 0.8);
+        assert_eq!("B", peaks[0].bayes.start.name);
+        assert_eq!("D", peaks[0].bayes.end.name);
+
+        let peaks = support_intervals(&qtls, 1.5, 0.9);
+        assert_eq!("B", peaks[0].bayes.start.name);
+        assert_eq!("E", peaks[0].bayes.end.name);
+    }
+
+    #[test]
+    fn support_intervals_hold_a_perfect_fit() {
+        let (dataset, traits) = fixture("BXD.txt");
+        let locus = find_locus(&dataset, "D1Mit1").unwrap();
+        // the trait is the genotype of a marker, so the marker fits it
+        // with an infinite LOD
+        let strain_ixs: Vec<_> = traits
+            .strains
+            .iter()
+            .map(|strain| {
+                dataset.strains().iter().position(|s| s == strain).unwrap()
+            })
+            .collect();
+        let values: Vec<_> = locus
+            .genotypes_subset(&strain_ixs)
+            .into_iter()
+            .map(Some)
+            .collect();
+
+        let qtls = regression(
+            &dataset,
+            &values,
+            None,
+            &traits.strains,
+            &Model::default(),
+        )
+        .unwrap();
+        let peaks = support_intervals(&qtls, 1.5, 0.95);
+
+        let first = &peaks[0];
+        assert_eq!("1", first.lod_drop.chromosome);
+        assert_eq!(f64::INFINITY, first.lod);
+        let perfect: Vec<_> = qtls
+            .iter()
+            .filter(|qtl| qtl.lod.is_infinite())
+            .map(|qtl| qtl.marker.centi_morgan)
+            .collect();
+        assert!(perfect.contains(&locus.marker.centi_morgan));
+        // both intervals hold all the loci with an infinite LOD, and
+        // nothing beyond their flanking markers
+        for interval in &[&first.lod_drop, &first.bayes] {
+            assert!(interval.start.centi_morgan <= perfect[0]);
+            assert!(interval.end.centi_morgan >= perfect[perfect.len() - 1]);
+        }
+        assert!(peaks[1..].iter().all(|peak| peak.lod.is_finite()));
+    }
+
+    #[test]
+    fn peaks_are_separated_by_a_lod_drop() {
+        let qtl = |name: &str, chromosome: &str, lod: f64| {
+            let marker = Marker {
+                name: name.to_string(),
+                centi_morgan: 0.0,
+                mega_basepair: None,
+                chromosome: chromosome.to_string(),
+            };
+            QTL::new(marker, lod * 2.0 * 10f64.ln(), 0.0, None, 0.0)
+        };
+
+        let qtls = vec![
+            qtl("A", "1", 1.0),
+            qtl("B", "1", 4.0),
+            qtl("C", "1", 3.5),
+            qtl("D", "1", 1.5),
+            qtl("E", "1", 3.0),
+            qtl("F", "1", 2.0),
+            qtl("G", "2", 0.5),
+        ];
+        let names = |peaks: Vec<&QTL>| -> Vec<String> {
+            peaks.iter().map(|qtl| qtl.marker.name.clone()).collect()
+        };
+
+        assert_eq!(vec!["B", "G"], names(peaks(&qtls, None)));
+        assert_eq!(vec!["B", "E", "G"], names(peaks(&qtls, Some(1.5))));
+        assert_eq!(vec!["B", "G"], names(peaks(&qtls, Some(2.0))));
+    }
+
+    #[test]
+    fn stepwise_selects_both_qtl_and_drops_a_linked_locus() {
+        let mut rng = Lcg::new(28);
+        let n = 60;
+        let (dataset, strains, genotypes) =
+            simulated_intercross("stepwise", n, &mut rng);
+
+        // QTL at M1_1 and M2_3; with this noise the strongest single
+        // locus is M1_2, which is linked to M1_1 but has no effect
+        let traits: Vec<_> = (0..n)
+            .map(|ix| {
+                Some(
+                    genotypes[1][ix]
+                        + 0.8 * genotypes[8][ix]
+                        + 1.5 * rng.noise(),
+                )
+            })
+            .collect();
+        let model = Model::default();
+
+        let qtls =
+            regression(&dataset, &traits, None, &strains, &model).unwrap();
+        let top = qtls
+            .iter()
+            .max_by(|x, y| x.lrs.partial_cmp(&y.lrs).unwrap())
+            .unwrap();
+        assert_eq!("M1_2", top.marker.name);
+
+        // forward selection adds M1_2 first, and backward elimination
+        // drops it once both QTL are in the model
+        let mqtl = stepwise(&dataset, &traits, None, &strains, &model, 8.0, 10)
+            .unwrap();
+        let names: Vec<_> =
+            mqtl.qtls.iter().map(|qtl| &qtl.marker.name[..]).collect();
+        assert_eq!(vec!["M2_3", "M1_1"], names);
+
+        // the effects and variance explained of each locus match a
+        // direct fit of both loci
+        let strain_ixs = dataset.strain_indices(&strains).unwrap();
+        let columns: Vec<Vec<Vec<f64>>> = names
+            .iter()
+            .map(|name| {
+                let locus = find_locus(&dataset, name).unwrap();
+                genotype_columns(&dataset, locus, &strain_ixs)
+            })
+            .collect();
+        let y: Vec<f64> = traits.iter().map(|t| t.unwrap()).collect();
+        let fit = |loci: &[&Vec<Vec<f64>>]| {
+            let columns: Vec<&[f64]> =
+                loci.iter().flat_map(|c| c.iter().map(|c| &c[..])).collect();
+            linalg::least_squares(&y, &linalg::design_matrix(n, &columns), None)
+                .unwrap()
+        };
+
+        let full = fit(&[&columns[0], &columns[1]]);
+        let tss = fit(&[]).rss;
+        let coefficients = full.coefficients.to_vec();
+        for (ix, qtl) in mqtl.qtls.iter().enumerate() {
+            assert!((qtl.additive - coefficients[1 + 2 * ix]).abs() < 1e-9);
+            assert!(
+                (qtl.dominance.unwrap() - coefficients[2 + 2 * ix]).abs()
+                    < 1e-9
+            );
+
+            let reduced = fit(&[&columns[1 - ix]]);
+            let explained = 100.0 * (reduced.rss - full.rss) / tss;
+            assert!((qtl.variance_explained - explained).abs() < 1e-9);
+            assert!(qtl.lrs > 8.0);
+        }
+        assert!((mqtl.lrs - n as f64 * (tss / full.rss).ln()).abs() < 1e-9);
+        assert!(
+            (mqtl.variance_explained - 100.0 * (1.0 - full.rss / tss)).abs()
+                < 1e-9
+        );
+    }
+
+    #[test]
+    fn stepwise_selects_pseudo_markers_by_position() {
+        let mut rng = Lcg::new(29);
+        let n = 80;
+        let (dataset, strains, _) =
+            simulated_intercross("stepwise-interval", n, &mut rng);
+        let dataset = dataset.interval_mapped_clone(1.0);
+        let strain_ixs = dataset.strain_indices(&strains).unwrap();
+
+        // QTL at pseudo-markers on chromosomes 1 and 2, which have the
+        // same name
+        let pseudo = |chromosome: &str, cm: f64| {
+            let locus = dataset
+                .genome
+                .chromosomes
+                .values()
+                .flatten()
+                .find(|locus| {
+                    locus.marker.chromosome == chromosome
+                        && (locus.marker.centi_morgan - cm).abs() < 1e-9
+                })
+                .unwrap();
+            assert!(locus.marker.is_pseudo());
+            locus.genotypes_subset(&strain_ixs)
+        };
+        let (first, second) = (pseudo("1", 10.0), pseudo("2", 50.0));
+        let traits: Vec<_> = (0..n)
+            .map(|ix| Some(first[ix] + second[ix] + 0.5 * rng.noise()))
+            .collect();
+
+        let mqtl = stepwise(
+            &dataset,
+            &traits,
+            None,
+            &strains,
+            &Model::default(),
+            8.0,
+            10,
+        )
+        .unwrap();
+        // both are found, at their own position and not that of the last
+        // pseudo-marker in the genome
+        let mut qtls: Vec<_> =
+            mqtl.qtls.iter().map(|qtl| &qtl.marker).collect();
+        qtls.sort_by(|x, y| x.chromosome.cmp(&y.chromosome));
+        assert_eq!(2, qtls.len());
+        for (marker, (chromosome, cm)) in
+            qtls.iter().zip(&[("1", 10.0), ("2", 50.0)])
+        {
+            assert!(marker.is_pseudo());
+            assert_eq!(*chromosome, marker.chromosome);
+            assert!((marker.centi_morgan - cm).abs() <= 2.0);
+        }
+    }
+
+    #[test]
+    fn missing_trait_values_are_left_out_of_the_scan() {
+        let (dataset, traits) = fixture("BXD.txt");
+        let values = &traits.traits[0].1;
+
+        // a traits file with some of the values of T1 missing
+        let missing = [2, 5, 11, 17];
+        let mut text = String::from("Trait");
+        for strain in traits.strains.iter() {
+            text += &format!("\t{}", strain);
+        }
+        text += "\nT1";
+        for (ix, value) in values.iter().enumerate() {
+            text += &match ix {
+                2 | 11 => "\tx".to_string(),
+                _ if missing.contains(&ix) => "\tNA".to_string(),
+                _ => format!("\t{}", value.unwrap()),
+            };
+        }
+        text += "\n";
+        let path = std::env::temp_dir()
+            .join(format!("qtlreaper-missing-{}.txt", std::process::id()));
+        std::fs::write(&path, text).unwrap();
+        let with_missing = Traits::read_file(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let n_missing = with_missing.traits[0]
+            .1
+            .iter()
+            .filter(|v| v.is_none())
+            .count();
+        assert_eq!(missing.len(), n_missing);
+
+        // the same values, of the observed strains only
+        let (strains, observed): (Vec<_>, Vec<_>) = traits
+            .strains
+            .iter()
+            .cloned()
+            .zip(values.iter().cloned())
+            .enumerate()
+            .filter(|(ix, _)| !missing.contains(ix))
+            .map(|(_, pair)| pair)
+            .unzip();
+
+        let model = Model::default();
+        let runs = [
+            (&with_missing.strains, &with_missing.traits[0].1),
+            (&strains, &observed),
+        ];
+        let scans: Vec<_> = runs
+            .iter()
+            .map(|(strains, values)| {
+                regression(&dataset, values, None, strains, &model).unwrap()
+            })
+            .collect();
+        for (a, b) in scans[0].iter().zip(scans[1].iter()) {
+            assert_eq!(a.marker.name, b.marker.name);
+            assert!((a.lrs - b.lrs).abs() < 1e-9);
+            assert!((a.additive - b.additive).abs() < 1e-9);
+        }
+
+        let permutations: Vec<_> = runs
+            .iter()
+            .map(|(strains, values)| {
+                permutation(
+                    &dataset,
+                    values,
+                    None,
+                    strains,
+                    &model,
+                    50,
+                    9,
+                    None,
+                    &[],
+                )
+                .unwrap()
+                .lrs
+            })
+            .collect();
+        assert_eq!(permutations[0], permutations[1]);
+    }
+
+    #[test]
+    fn strains_must_be_in_the_covariates_file() {
+        let (dataset, traits) = fixture("BXD.txt");
+        let mut values = traits.traits[0].1.clone();
+        let column = |strains: &[String]| {
+            strains.iter().map(|_| Some(1.0)).collect::<Vec<_>>()
+        };
+        // the first strain of the traits has no covariates
+        let strains = traits.strains[1..].to_vec();
+        let model = Model {
+            covariates: Some(Covariates {
+                columns: vec![("age".to_string(), column(&strains))],
+                strains,
+            }),
+            ..Model::default()
+        };
+
+        match regression(&dataset, &values, None, &traits.strains, &model) {
+            Err(Error::MissingCovariates { strain }) => {
+                assert_eq!(traits.strains[0], strain)
+            }
+            _ => panic!("expected a missing covariates error"),
+        }
+
+        // unless its trait value is missing too
+        values[0] = None;
+        assert!(regression(&dataset, &values, None, &traits.strains, &model)
+            .is_ok());
+    }
+
+    #[test]
+    fn scantwo_permutations_give_thresholds_for_any_number_of_threads() {
+        let mut rng = Lcg::new(21);
+        let (dataset, strains, genotypes) =
+            simulated_intercross("scantwo", 40, &mut rng);
+        let values: Vec<_> = (0..40)
+            .map(|ix| Some(genotypes[2][ix] * genotypes[8][ix] + rng.noise()))
+            .collect();
+
+        let permutations = |n_threads: usize| {
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(n_threads)
+                .build()
+                .unwrap()
+                .install(|| {
+                    scantwo_permutation(
+                        &dataset,
+                        &values,
+                        None,
+                        &strains,
+                        &Model::default(),
+                        20,
+                        4,
+                        &[0.05],
+                    )
+                    .unwrap()
+                })
+        };
+
+        let single = permutations(1);
+        assert_eq!(20, single.full.len());
+        assert_eq!(
+            threshold(&single.full, 0.05),
+            single.full_thresholds.significant[0].lrs
+        );
+        assert_eq!(
+            threshold(&single.interaction, SUGGESTIVE_ALPHA),
+            single.interaction_thresholds.suggestive.lrs
+        );
+
+        let parallel = permutations(4);
+        assert_eq!(single.full, parallel.full);
+        assert_eq!(single.interaction, parallel.interaction);
+    }
+
+    #[test]
+    fn permutation_maxima_use_the_control_and_dominance_model() {
+        let (bxd, traits) = fixture("BXD.txt");
+
+        let mut rng = Lcg::new(5);
+        let (f2, f2_strains, genotypes) =
+            simulated_intercross("permutation", 40, &mut rng);
+        let f2_traits: Vec<_> = (0..40)
+            .map(|ix| Some(genotypes[3][ix] + rng.noise()))
+            .collect();
+
+        let cases = [
+            (&bxd, &traits.traits[0].1, &traits.strains, "D1Mit294"),
+            (&f2, &f2_traits, &f2_strains, "M2_0"),
+        ];
+
+        for (dataset, values, strains, control) in cases.iter() {
+            let model = Model {
+                control: vec![control.to_string()],
+                ..Model::default()
+            };
+            let (n_perms, seed) = (20, 11);
+            let permu = permutation(
+                dataset,
+                values,
+                None,
+                strains,
+                &model,
+                n_perms,
+                seed,
+                None,
+                &[],
+            )
+            .unwrap();
+
+            // each permutation scanned directly with the control marker
+            let (observed, _) = Observed::with_cofactors(
+                dataset, values, None, strains, &model,
+            )
+            .unwrap();
+            let strain_ixs = &observed.strain_ixs;
+            let n = strain_ixs.len();
+            let control = find_locus(dataset, control).unwrap();
+            let control = genotype_columns(dataset, control, strain_ixs);
+            let control: Vec<_> = control.iter().map(|c| &c[..]).collect();
+
+            let mut expected: Vec<f64> = (0..n_perms)
+                .map(|stream| {
+                    let mut order = vec![0; n];
+                    permuted_mut(
+                        &mut order,
+                        &mut stream_rng(seed, Domain::Permutation, stream),
+                    );
+                    let mut p_traits = vec![0.0; n];
+                    gather(&observed.traits, &order, &mut p_traits);
+
+                    dataset
+                        .genome
+                        .chromosomes
+                        .values()
+                        .flatten()
+                        .map(|locus| {
+                            let columns =
+                                genotype_columns(dataset, locus, strain_ixs);
+                            locus_regression(
+                                &p_traits,
+                                &columns[0],
+                                columns.get(1).map(|d| &d[..]),
+                                &control,
+                                None,
+                            )
+                            .lrs
+                        })
+                        .fold(0.0, f64::max)
+                })
+                .collect();
+            expected.sort_by(|x, y| x.partial_cmp(y).unwrap());
+
+            assert_eq!(expected.len(), permu.lrs.len());
+            for (e, p) in expected.iter().zip(permu.lrs.iter()) {
+                assert!((e - p).abs() < 1e-6, "{} != {}", e, p);
+            }
+        }
+    }
+}